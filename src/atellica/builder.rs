@@ -0,0 +1,421 @@
+use std::fmt;
+
+use super::{
+    enums::{
+        AddQueueCommandStatusValues, CarrierOccupancy, InterfacePositionIndex, SamplePriority,
+        TubeType,
+    },
+    info::Info,
+    message::{Message, Payload},
+    messages::add_queue::{
+        add_queue_request::AddQueueRequest, add_queue_response::AddQueueResponse,
+    },
+    urap_tube::{TubeError, UrapTube},
+    utils::{check_sample_id, SampleIdError, SampleIdPolicy},
+};
+
+/// Why a builder could not build its message
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuildError {
+    /// A field the message can't do without was never set.
+    Missing(&'static str),
+    /// The sample id can't be sent.
+    SampleId(SampleIdError),
+    /// A tube field is out of range.
+    Tube(TubeError),
+    /// The message doesn't encode, e.g. because its body is too long.
+    Encode,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Missing(field) => write!(f, "missing {}", field),
+            BuildError::SampleId(err) => write!(f, "{}", err),
+            BuildError::Tube(err) => write!(f, "{}", err),
+            BuildError::Encode => write!(f, "message does not encode"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Sets the header fields of a message, then hands over to the builder of
+/// its payload
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessageBuilder {
+    instrument_id: u8,
+    sequence_id: u16,
+    return_sequence_id: u16,
+    sample_id_policy: SampleIdPolicy,
+}
+
+impl Message {
+    // Starts a message for instrument 0 with sequence ids 0, answering
+    // nothing. Sequence ids are usually given by a SequenceAllocator once
+    // the message is built.
+    pub fn builder() -> MessageBuilder {
+        MessageBuilder::default()
+    }
+}
+
+impl MessageBuilder {
+    pub fn instrument(mut self, instrument_id: u8) -> Self {
+        self.instrument_id = instrument_id;
+        self
+    }
+
+    pub fn sequence(mut self, sequence_id: u16) -> Self {
+        self.sequence_id = sequence_id;
+        self
+    }
+
+    pub fn return_sequence(mut self, return_sequence_id: u16) -> Self {
+        self.return_sequence_id = return_sequence_id;
+        self
+    }
+
+    // Addresses the message to the instrument that sent `request`, as its
+    // answer.
+    pub fn answering(self, request: &Message) -> Self {
+        self.instrument(request.header().instrument_id())
+            .return_sequence(request.header().sequence_id)
+    }
+
+    // Chooses what happens to sample ids set later on that are too long.
+    pub fn sample_id_policy(mut self, policy: SampleIdPolicy) -> Self {
+        self.sample_id_policy = policy;
+        self
+    }
+
+    pub fn add_queue_request(self) -> PayloadBuilder<AddQueueRequest> {
+        PayloadBuilder::new(self, Some("tube"))
+    }
+
+    pub fn add_queue_response(self) -> PayloadBuilder<AddQueueResponse> {
+        PayloadBuilder::new(self, Some("sample id"))
+    }
+
+}
+
+/// Sets the fields of a payload; fields left alone keep their default value
+#[derive(Clone, Debug)]
+pub struct PayloadBuilder<T> {
+    message: MessageBuilder,
+    payload: T,
+    // A required field not set yet.
+    missing: Option<&'static str>,
+    // The first error met by a setter, reported by build.
+    error: Option<BuildError>,
+}
+
+impl<T: Default> PayloadBuilder<T> {
+    fn new(message: MessageBuilder, missing: Option<&'static str>) -> Self {
+        Self {
+            message,
+            payload: T::default(),
+            missing,
+            error: None,
+        }
+    }
+}
+
+impl<T> PayloadBuilder<T> {
+    // Returns the sample id to store, after applying the policy, or the
+    // current one if it can't be sent.
+    fn checked_sample_id(&mut self, sample_id: &str, current: &str) -> String {
+        match check_sample_id(sample_id, self.message.sample_id_policy) {
+            Ok("") => {
+                self.error.get_or_insert(BuildError::Missing("sample id"));
+                current.to_string()
+            }
+            Ok(sample_id) => {
+                self.missing = None;
+                sample_id.to_string()
+            }
+            Err(err) => {
+                self.error.get_or_insert(BuildError::SampleId(err));
+                current.to_string()
+            }
+        }
+    }
+
+    // Returns the tube built by `build`, or the current one if it failed.
+    fn built_tube(
+        &mut self,
+        build: impl FnOnce(TubeBuilder) -> TubeBuilder,
+        current: &UrapTube,
+    ) -> UrapTube {
+        let tube = TubeBuilder {
+            sample_id_policy: self.message.sample_id_policy,
+            ..Default::default()
+        };
+        match build(tube).build() {
+            Ok(tube) => {
+                self.missing = None;
+                tube
+            }
+            Err(err) => {
+                self.error.get_or_insert(err);
+                current.clone()
+            }
+        }
+    }
+}
+
+impl<T> PayloadBuilder<T>
+where
+    T: Info,
+    Payload: From<T>,
+{
+    // Returns the message, checked to encode so that it can be sent.
+    pub fn build(self) -> Result<Message, BuildError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        if let Some(field) = self.missing {
+            return Err(BuildError::Missing(field));
+        }
+        let mut message = Message::new(
+            self.message.return_sequence_id,
+            self.message.instrument_id,
+            Payload::from(self.payload),
+        );
+        message.set_sequence_id(self.message.sequence_id);
+        message.encode().ok_or(BuildError::Encode)?;
+        Ok(message)
+    }
+}
+
+impl PayloadBuilder<AddQueueRequest> {
+    pub fn ip(mut self, ip: InterfacePositionIndex) -> Self {
+        self.payload = AddQueueRequest::new(ip, self.payload.tube());
+        self
+    }
+
+    pub fn tube(mut self, build: impl FnOnce(TubeBuilder) -> TubeBuilder) -> Self {
+        let current = self.payload.tube().clone();
+        let tube = self.built_tube(build, &current);
+        self.payload = AddQueueRequest::new(self.payload.interface_position_index(), &tube);
+        self
+    }
+}
+
+impl PayloadBuilder<AddQueueResponse> {
+    pub fn ip(mut self, ip: InterfacePositionIndex) -> Self {
+        let p = &self.payload;
+        self.payload = AddQueueResponse::new(ip, p.sample_id().to_string(), p.command_status());
+        self
+    }
+
+    pub fn sample_id(mut self, sample_id: &str) -> Self {
+        let current = self.payload.sample_id().to_string();
+        let sample_id = self.checked_sample_id(sample_id, &current);
+        let p = &self.payload;
+        self.payload =
+            AddQueueResponse::new(p.interface_position_index(), sample_id, p.command_status());
+        self
+    }
+
+    pub fn command_status(mut self, command_status: AddQueueCommandStatusValues) -> Self {
+        let p = &self.payload;
+        self.payload = AddQueueResponse::new(
+            p.interface_position_index(),
+            p.sample_id().to_string(),
+            command_status,
+        );
+        self
+    }
+}
+
+/// Sets the fields of a tube. Tubes are capped and routine unless told
+/// otherwise; the sample id is required.
+#[derive(Clone, Debug)]
+pub struct TubeBuilder {
+    carrier_occupancy: CarrierOccupancy,
+    tube_type: TubeType,
+    sample_id: String,
+    sample_priority: SamplePriority,
+    height_mm: f32,
+    diameter_mm: f32,
+    sample_id_policy: SampleIdPolicy,
+}
+
+impl Default for TubeBuilder {
+    fn default() -> Self {
+        Self {
+            carrier_occupancy: CarrierOccupancy::CappedTube,
+            tube_type: TubeType::default(),
+            sample_id: String::new(),
+            sample_priority: SamplePriority::Routine,
+            height_mm: 0.0,
+            diameter_mm: 0.0,
+            sample_id_policy: SampleIdPolicy::default(),
+        }
+    }
+}
+
+impl UrapTube {
+    pub fn builder() -> TubeBuilder {
+        TubeBuilder::default()
+    }
+}
+
+impl TubeBuilder {
+    pub fn occupancy(mut self, carrier_occupancy: CarrierOccupancy) -> Self {
+        self.carrier_occupancy = carrier_occupancy;
+        self
+    }
+
+    pub fn tube_type(mut self, tube_type: TubeType) -> Self {
+        self.tube_type = tube_type;
+        self
+    }
+
+    pub fn sample_id(mut self, sample_id: &str) -> Self {
+        self.sample_id = sample_id.to_string();
+        self
+    }
+
+    pub fn priority(mut self, sample_priority: SamplePriority) -> Self {
+        self.sample_priority = sample_priority;
+        self
+    }
+
+    pub fn height_mm(mut self, mm: f32) -> Self {
+        self.height_mm = mm;
+        self
+    }
+
+    pub fn diameter_mm(mut self, mm: f32) -> Self {
+        self.diameter_mm = mm;
+        self
+    }
+
+    pub fn sample_id_policy(mut self, policy: SampleIdPolicy) -> Self {
+        self.sample_id_policy = policy;
+        self
+    }
+
+    pub fn build(self) -> Result<UrapTube, BuildError> {
+        let sample_id = check_sample_id(&self.sample_id, self.sample_id_policy)
+            .map_err(BuildError::SampleId)?;
+        if sample_id.is_empty() {
+            return Err(BuildError::Missing("sample id"));
+        }
+        let mut tube = UrapTube::new(
+            self.carrier_occupancy,
+            self.tube_type,
+            sample_id.to_string(),
+            self.sample_priority,
+            0,
+            0,
+        );
+        tube.set_tube_height_mm(self.height_mm)
+            .map_err(BuildError::Tube)?;
+        tube.set_tube_diameter_mm(self.diameter_mm)
+            .map_err(BuildError::Tube)?;
+        Ok(tube)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atellica::enums::MessageType;
+
+    #[test]
+    fn add_queue_request_test() {
+        let message = Message::builder()
+            .instrument(10)
+            .sequence(3)
+            .add_queue_request()
+            .ip(InterfacePositionIndex::IP1)
+            .tube(|t| {
+                t.sample_id("X")
+                    .priority(SamplePriority::STAT)
+                    .height_mm(100.0)
+                    .diameter_mm(13.0)
+            })
+            .build()
+            .unwrap();
+        assert_eq!(message.header().instrument_id(), 10);
+        assert_eq!(message.header().sequence_id, 3);
+        assert_eq!(message.header().message_type, MessageType::AddQueueRequest);
+        match message.payload() {
+            Payload::AddQueueRequest(request) => {
+                assert_eq!(
+                    request.interface_position_index(),
+                    InterfacePositionIndex::IP1
+                );
+                assert_eq!(request.tube().sample_id, "X");
+                assert_eq!(request.tube().sample_priority(), SamplePriority::STAT);
+                assert_eq!(
+                    request.tube().carrier_occupancy(),
+                    CarrierOccupancy::CappedTube
+                );
+                assert_eq!(request.tube().tube_diameter(), 130);
+            }
+            payload => panic!("unexpected payload {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn answering_test() {
+        let request = Message::builder()
+            .instrument(4)
+            .sequence(0x1234)
+            .add_queue_request()
+            .tube(|t| t.sample_id("S-1"))
+            .build()
+            .unwrap();
+        let response = Message::builder()
+            .answering(&request)
+            .add_queue_response()
+            .sample_id("S-1")
+            .build()
+            .unwrap();
+        assert!(request.is_my_response(&response));
+        assert_eq!(response.header().instrument_id(), 4);
+    }
+
+    #[test]
+    fn validation_test() {
+        assert_eq!(
+            Message::builder().add_queue_response().build().unwrap_err(),
+            BuildError::Missing("sample id")
+        );
+        assert_eq!(
+            Message::builder().add_queue_request().build().unwrap_err(),
+            BuildError::Missing("tube")
+        );
+        assert_eq!(
+            Message::builder()
+                .add_queue_request()
+                .tube(|t| t.sample_id("S").height_mm(1000.0))
+                .build()
+                .unwrap_err(),
+            BuildError::Tube(TubeError::HeightOutOfRange(1000.0))
+        );
+
+        let long = "x".repeat(300);
+        assert_eq!(
+            Message::builder()
+                .add_queue_response()
+                .sample_id(&long)
+                .build()
+                .unwrap_err(),
+            BuildError::SampleId(SampleIdError::TooLong(300))
+        );
+        let message = Message::builder()
+            .sample_id_policy(SampleIdPolicy::Truncate)
+            .add_queue_response()
+            .sample_id(&long)
+            .build()
+            .unwrap();
+        match message.payload() {
+            Payload::AddQueueResponse(response) => assert_eq!(response.sample_id().len(), 255),
+            payload => panic!("unexpected payload {:?}", payload),
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use num_enum::{IntoPrimitive, TryFromPrimitive};
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
+use std::fmt;
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum InterfacePositionIndex {
     #[default]
@@ -8,49 +10,159 @@ pub enum InterfacePositionIndex {
     IP1,
 }
 
-#[derive(Debug, Clone, Copy, Default, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum CarrierOccupancy {
-    #[default]
     EmptyCarrier,
     UncappedTube,
     CappedTube,
+    /// A value this crate does not know, kept as received.
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+// num_enum takes #[default] as another name for catch_all, so Default
+// can't be derived for any enum with a catch_all.
+#[allow(clippy::derivable_impls)]
+impl Default for CarrierOccupancy {
+    fn default() -> Self {
+        CarrierOccupancy::EmptyCarrier
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default, IntoPrimitive, TryFromPrimitive)]
+/// Tube in a carrier. Greiner (0) is the only type the interface description
+/// names; other values are kept as received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum TubeType {
-    #[default]
     Greiner,
+    /// A value this crate does not know, kept as received.
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for TubeType {
+    fn default() -> Self {
+        TubeType::Greiner
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum SamplePriority {
-    #[default]
     Undefined,
     Routine,
     STAT,
+    /// A value this crate does not know, kept as received.
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for SamplePriority {
+    fn default() -> Self {
+        SamplePriority::Undefined
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
+#[allow(clippy::enum_variant_names)]
 pub enum MessageType {
-    #[default]
     NoMessageType,
     AddQueueRequest = 0x0405,
     AddQueueResponse = 0x0406,
+    /// A type this crate does not know, kept so that the frame is not lost.
+    #[num_enum(catch_all)]
+    Unknown(u16),
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for MessageType {
+    fn default() -> Self {
+        MessageType::NoMessageType
+    }
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageType::Unknown(x) => write!(f, "Unknown(0x{:04x})", x),
+            x => write!(f, "{:?}", x),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum AddQueueCommandStatusValues {
     #[default]
     Ok,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DecodeError {
     MessageTypeError,
     NoSTX,
+    NoETX,
+    TooShort,
+    InvalidField,
+    /// The CRC in the footer doesn't match the one computed over the frame.
+    BadCrc {
+        expected: u16,
+        found: u16,
+    },
+    /// A length prefixed field runs past the end of the body.
+    FieldTooLong {
+        length: usize,
+        remaining: usize,
+    },
 }
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::MessageTypeError => write!(f, "unknown message type"),
+            DecodeError::NoSTX => write!(f, "frame does not start with STX"),
+            DecodeError::NoETX => write!(f, "frame does not end with ETX"),
+            DecodeError::TooShort => write!(f, "frame is too short"),
+            DecodeError::InvalidField => write!(f, "invalid field value"),
+            DecodeError::BadCrc { expected, found } => {
+                write!(f, "bad CRC 0x{:04x} (expected 0x{:04x})", found, expected)
+            }
+            DecodeError::FieldTooLong { length, remaining } => write!(
+                f,
+                "field of {} bytes with only {} left in the body",
+                length, remaining
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A DecodeError and where it was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameError {
+    /// Offset, from STX, of the byte at fault, or of the end of the frame
+    /// when it is too short.
+    pub offset: usize,
+    pub error: DecodeError,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.error, self.offset)
+    }
+}
+
+impl std::error::Error for FrameError {}
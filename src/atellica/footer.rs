@@ -2,9 +2,46 @@ use bytes::{BufMut, BytesMut};
 
 use super::enums::DecodeError;
 
-const ETX: u8 = 0x03;
+pub const ETX: u8 = 0x03;
+pub const FOOTER_LEN: usize = 3;
+
+// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF, no reflection.
+// The interface description doesn't say how the CRC is computed; this is
+// the common choice for such framing, assumed until frames captured from an
+// instrument confirm it. The link layer only acts on a mismatch with
+// check_crc, and the decode command only reports one with --check-crc.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ (*byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+// Checks the CRC of a whole frame, which covers the bytes after STX up to
+// the footer.
+pub fn check_crc(src: &[u8]) -> Result<(), DecodeError> {
+    if src.len() < 1 + FOOTER_LEN {
+        return Err(DecodeError::TooShort);
+    }
+    let start = src.len() - FOOTER_LEN;
+    if src[start + 2] != ETX {
+        return Err(DecodeError::NoETX);
+    }
+    let expected = crc16(&src[1..start]);
+    let found = u16::from_be_bytes([src[start], src[start + 1]]);
+    if found != expected {
+        return Err(DecodeError::BadCrc { expected, found });
+    }
+    Ok(())
+}
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Footer {
     crc: u16,
     etx: u8,
@@ -14,6 +51,15 @@ impl Footer {
     pub fn new() -> Self {
         Self { crc: 0, etx: ETX }
     }
+
+    pub fn crc(&self) -> u16 {
+        self.crc
+    }
+
+    pub fn set_crc(&mut self, crc: u16) {
+        self.crc = crc;
+    }
+
     pub fn encode(&mut self) -> Option<BytesMut> {
         let mut dst: BytesMut = BytesMut::with_capacity(size_of::<Self>());
 
@@ -23,8 +69,49 @@ impl Footer {
         Some(dst)
     }
 
+    // Reads the footer from the end of a whole frame.
     pub fn decode(&mut self, src: &BytesMut) -> Result<(), DecodeError> {
-        println!("{:?}", src);
+        let start = src
+            .len()
+            .checked_sub(FOOTER_LEN)
+            .ok_or(DecodeError::NoETX)?;
+        if src[start + 2] != ETX {
+            return Err(DecodeError::NoETX);
+        }
+        self.crc = u16::from_be_bytes(src[start..start + 2].try_into().unwrap());
+        self.etx = src[start + 2];
+
         Ok(())
     }
+
+    // Inverts every bit of the CRC, so that the frame fails its check.
+    pub fn flip_crc(&mut self) {
+        self.crc = !self.crc;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_test() {
+        // The check value of CRC-16/CCITT-FALSE.
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(b""), 0xFFFF);
+    }
+
+    #[test]
+    fn check_crc_test() {
+        let mut frame = vec![0x02, b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9'];
+        frame.extend_from_slice(&[0x29, 0xB1, ETX]);
+        assert_eq!(check_crc(&frame), Ok(()));
+
+        frame[1] = b'0';
+        assert!(matches!(
+            check_crc(&frame),
+            Err(DecodeError::BadCrc { found: 0x29B1, .. })
+        ));
+        assert_eq!(check_crc(&frame[..3]), Err(DecodeError::TooShort));
+    }
 }
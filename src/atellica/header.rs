@@ -1,19 +1,34 @@
 use super::enums::{DecodeError, MessageType};
 use bytes::{BufMut, BytesMut};
 
-const STX: u8 = 0x02;
+pub const STX: u8 = 0x02;
+pub const HEADER_LEN: usize = 18;
 
 #[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
+    #[cfg_attr(feature = "serde", serde(skip, default = "stx"))]
     stx: u8,
+    // Length of the whole frame, from STX to ETX, filled in by `encode`;
+    // authored messages can leave it out. No interface specification is at
+    // hand to confirm that it doesn't count the body alone; captured frames
+    // should settle it.
+    #[cfg_attr(feature = "serde", serde(default))]
     message_length: u16,
     pub sequence_id: u16,
     pub return_sequence_id: u16,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub message_type: MessageType,
+    #[cfg_attr(feature = "serde", serde(default))]
     time_stamp: [u8; 8],
     instrument_id: u8,
 }
 
+#[cfg(feature = "serde")]
+fn stx() -> u8 {
+    STX
+}
+
 impl Header {
     pub fn new(message_type: MessageType, return_sequence_id: u16, instrument_id: u8) -> Self {
         Self {
@@ -25,6 +40,22 @@ impl Header {
         }
     }
 
+    pub fn message_length(&self) -> u16 {
+        self.message_length
+    }
+
+    pub fn set_message_length(&mut self, message_length: u16) {
+        self.message_length = message_length;
+    }
+
+    pub fn time_stamp(&self) -> [u8; 8] {
+        self.time_stamp
+    }
+
+    pub fn instrument_id(&self) -> u8 {
+        self.instrument_id
+    }
+
     pub fn encode(&mut self) -> Option<BytesMut> {
         let mut dst: BytesMut = BytesMut::with_capacity(size_of::<Self>());
 
@@ -40,7 +71,9 @@ impl Header {
     }
 
     pub fn decode(&mut self, src: &BytesMut) -> Result<(), DecodeError> {
-        println!("Header::decoder -> {:?}", src);
+        if src.len() < HEADER_LEN {
+            return Err(DecodeError::TooShort);
+        }
         if src[0] != STX {
             return Err(DecodeError::NoSTX);
         }
@@ -49,8 +82,8 @@ impl Header {
         self.sequence_id = u16::from_be_bytes(src[3..5].try_into().unwrap());
         self.return_sequence_id = u16::from_be_bytes(src[5..7].try_into().unwrap());
         let num = u16::from_be_bytes(src[7..9].try_into().unwrap());
-        self.message_type = MessageType::try_from(num).map_err(|_x| DecodeError::NoSTX)?;
-        //self.time_stamp = src[9..17].;
+        self.message_type = MessageType::from(num);
+        self.time_stamp = src[9..17].try_into().unwrap();
         self.instrument_id = src[17];
 
         Ok(())
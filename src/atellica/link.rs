@@ -0,0 +1,334 @@
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::enums::DecodeError;
+use super::footer;
+use super::header::STX;
+
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+
+/// How long frames wait for their acknowledgement, and how often they are
+/// sent again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinkSettings {
+    /// Time the peer has to answer a frame with ACK or NAK.
+    pub timeout: Duration,
+    /// Retransmissions of a frame before giving up on it.
+    pub retries: u32,
+    /// Answer frames whose CRC doesn't match with NAK. Off by default, as
+    /// the CRC algorithm is an assumption, see `footer::crc16`, so frames
+    /// with a bad CRC are ACKed unless this is set; frames without ETX are
+    /// rejected either way.
+    pub check_crc: bool,
+}
+
+impl Default for LinkSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(1000),
+            retries: 3,
+            check_crc: false,
+        }
+    }
+}
+
+/// Something that happened on the link
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The peer acknowledged the frame with this sequence id.
+    Acked(u16),
+    /// The peer rejected the frame, which is sent again.
+    Nacked { sequence_id: u16, attempt: u32 },
+    /// The peer didn't answer the frame in time, it is sent again.
+    TimedOut { sequence_id: u16, attempt: u32 },
+    /// The frame was never acknowledged and is dropped.
+    GaveUp(u16),
+    /// A received frame failed its checks and was answered with NAK.
+    Rejected(DecodeError),
+    /// An ACK or NAK arrived while no frame was waiting for one.
+    Unexpected(u8),
+}
+
+impl fmt::Display for LinkEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkEvent::Acked(id) => write!(f, "ACK for 0x{:04x}", id),
+            LinkEvent::Nacked {
+                sequence_id,
+                attempt,
+            } => write!(f, "NAK for 0x{:04x}, retry {}", sequence_id, attempt),
+            LinkEvent::TimedOut {
+                sequence_id,
+                attempt,
+            } => write!(f, "no answer for 0x{:04x}, retry {}", sequence_id, attempt),
+            LinkEvent::GaveUp(id) => write!(f, "gave up on 0x{:04x}", id),
+            LinkEvent::Rejected(err) => write!(f, "sent NAK: {}", err),
+            LinkEvent::Unexpected(ACK) => write!(f, "unexpected ACK"),
+            LinkEvent::Unexpected(NAK) => write!(f, "unexpected NAK"),
+            LinkEvent::Unexpected(byte) => write!(f, "unexpected 0x{:02x}", byte),
+        }
+    }
+}
+
+/// What the owner of the port has to do for the link
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkAction {
+    /// Bytes to write to the port.
+    Write(Bytes),
+    /// Something to report.
+    Event(LinkEvent),
+}
+
+// The frame sent last, until the peer acknowledges it.
+#[derive(Debug)]
+struct Outstanding {
+    frame: Bytes,
+    retries: u32,
+    deadline: Instant,
+}
+
+impl Outstanding {
+    fn sequence_id(&self) -> u16 {
+        match self.frame.get(3..5) {
+            Some(id) => u16::from_be_bytes([id[0], id[1]]),
+            None => 0,
+        }
+    }
+}
+
+/// The ACK/NAK link layer of one port. It only decides what to write and
+/// report; the caller does the I/O and calls `poll` once `deadline` has
+/// passed.
+#[derive(Debug)]
+pub struct Link {
+    settings: LinkSettings,
+    outstanding: Option<Outstanding>,
+    queue: VecDeque<Bytes>,
+}
+
+impl Link {
+    pub fn new(settings: LinkSettings) -> Self {
+        Self {
+            settings,
+            outstanding: None,
+            queue: VecDeque::new(),
+        }
+    }
+
+    // Queues a frame. It is written at once unless an earlier frame is still
+    // waiting for its acknowledgement.
+    pub fn send(&mut self, frame: Bytes, now: Instant) -> Vec<LinkAction> {
+        self.queue.push_back(frame);
+        let mut actions = Vec::new();
+        self.next(now, &mut actions);
+        actions
+    }
+
+    // Handles a frame received from the peer: answers Atellica frames with
+    // ACK or NAK, and ACK or NAK with the next frame or a retransmission.
+    pub fn receive(&mut self, frame: &[u8], now: Instant) -> Vec<LinkAction> {
+        let mut actions = Vec::new();
+        match frame {
+            [ACK] => match self.outstanding.take() {
+                Some(outstanding) => {
+                    actions.push(LinkAction::Event(LinkEvent::Acked(
+                        outstanding.sequence_id(),
+                    )));
+                    self.next(now, &mut actions);
+                }
+                None => actions.push(LinkAction::Event(LinkEvent::Unexpected(ACK))),
+            },
+            [NAK] => match &self.outstanding {
+                Some(outstanding) => {
+                    let event = LinkEvent::Nacked {
+                        sequence_id: outstanding.sequence_id(),
+                        attempt: outstanding.retries + 1,
+                    };
+                    self.retransmit(event, now, &mut actions);
+                }
+                None => actions.push(LinkAction::Event(LinkEvent::Unexpected(NAK))),
+            },
+            [STX, ..] => match footer::check_crc(frame) {
+                Err(DecodeError::BadCrc { .. }) if !self.settings.check_crc => {
+                    actions.push(LinkAction::Write(Bytes::from_static(&[ACK])))
+                }
+                Ok(()) => actions.push(LinkAction::Write(Bytes::from_static(&[ACK]))),
+                Err(err) => {
+                    actions.push(LinkAction::Write(Bytes::from_static(&[NAK])));
+                    actions.push(LinkAction::Event(LinkEvent::Rejected(err)));
+                }
+            },
+            // Noise between frames isn't answered.
+            _ => {}
+        }
+        actions
+    }
+
+    // Returns when the frame waiting for its acknowledgement times out.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.outstanding
+            .as_ref()
+            .map(|outstanding| outstanding.deadline)
+    }
+
+    // Retransmits the waiting frame if its deadline has passed.
+    pub fn poll(&mut self, now: Instant) -> Vec<LinkAction> {
+        let mut actions = Vec::new();
+        if let Some(outstanding) = &self.outstanding {
+            if outstanding.deadline <= now {
+                let event = LinkEvent::TimedOut {
+                    sequence_id: outstanding.sequence_id(),
+                    attempt: outstanding.retries + 1,
+                };
+                self.retransmit(event, now, &mut actions);
+            }
+        }
+        actions
+    }
+
+    // Returns true when every frame was acknowledged or given up on.
+    pub fn is_idle(&self) -> bool {
+        self.outstanding.is_none() && self.queue.is_empty()
+    }
+
+    // Writes the next queued frame if none is waiting.
+    fn next(&mut self, now: Instant, actions: &mut Vec<LinkAction>) {
+        if self.outstanding.is_some() {
+            return;
+        }
+        if let Some(frame) = self.queue.pop_front() {
+            actions.push(LinkAction::Write(frame.clone()));
+            self.outstanding = Some(Outstanding {
+                frame,
+                retries: 0,
+                deadline: now + self.settings.timeout,
+            });
+        }
+    }
+
+    // Sends the waiting frame again, or gives up on it once it used all of
+    // its retries.
+    fn retransmit(&mut self, event: LinkEvent, now: Instant, actions: &mut Vec<LinkAction>) {
+        let Some(outstanding) = &mut self.outstanding else {
+            return;
+        };
+        if outstanding.retries >= self.settings.retries {
+            actions.push(LinkAction::Event(LinkEvent::GaveUp(
+                outstanding.sequence_id(),
+            )));
+            self.outstanding = None;
+            self.next(now, actions);
+            return;
+        }
+        outstanding.retries += 1;
+        outstanding.deadline = now + self.settings.timeout;
+        actions.push(LinkAction::Event(event));
+        actions.push(LinkAction::Write(outstanding.frame.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atellica::enums::{AddQueueCommandStatusValues, InterfacePositionIndex};
+    use crate::atellica::{AddQueueResponse, Info, Message, Payload};
+
+    fn frame(sequence_id: u16) -> Bytes {
+        let response = AddQueueResponse::new(
+            InterfacePositionIndex::IP0,
+            "S-1".to_string(),
+            AddQueueCommandStatusValues::Ok,
+        );
+        let mut message = Message::new(0, 10, Payload::AddQueueResponse(response));
+        message.set_sequence_id(sequence_id);
+        message.encode().unwrap().freeze()
+    }
+
+    fn settings() -> LinkSettings {
+        LinkSettings {
+            timeout: Duration::from_millis(100),
+            retries: 1,
+            check_crc: true,
+        }
+    }
+
+    #[test]
+    fn receive_test() {
+        let mut link = Link::new(settings());
+        let now = Instant::now();
+        let good = frame(1);
+        assert_eq!(
+            link.receive(&good, now),
+            vec![LinkAction::Write(Bytes::from_static(&[ACK]))]
+        );
+
+        let mut bad = good.to_vec();
+        bad[4] ^= 0xFF;
+        assert!(matches!(
+            &link.receive(&bad, now)[..],
+            [
+                LinkAction::Write(nak),
+                LinkAction::Event(LinkEvent::Rejected(DecodeError::BadCrc { .. })),
+            ] if nak[..] == [NAK]
+        ));
+
+        assert_eq!(
+            link.receive(&[ACK], now),
+            vec![LinkAction::Event(LinkEvent::Unexpected(ACK))]
+        );
+        assert_eq!(
+            link.receive(&[NAK], now),
+            vec![LinkAction::Event(LinkEvent::Unexpected(NAK))]
+        );
+        assert_eq!(LinkEvent::Unexpected(NAK).to_string(), "unexpected NAK");
+        assert!(link.receive(b"noise", now).is_empty());
+
+        let mut unchecked = Link::new(LinkSettings {
+            check_crc: false,
+            ..settings()
+        });
+        assert_eq!(
+            unchecked.receive(&bad, now),
+            vec![LinkAction::Write(Bytes::from_static(&[ACK]))]
+        );
+    }
+
+    #[test]
+    fn retransmit_test() {
+        let mut link = Link::new(settings());
+        let now = Instant::now();
+        assert_eq!(link.send(frame(1), now), vec![LinkAction::Write(frame(1))]);
+        // The second frame waits for the first one.
+        assert!(link.send(frame(2), now).is_empty());
+        assert_eq!(link.deadline(), Some(now + Duration::from_millis(100)));
+
+        assert_eq!(
+            link.receive(&[NAK], now),
+            vec![
+                LinkAction::Event(LinkEvent::Nacked {
+                    sequence_id: 1,
+                    attempt: 1
+                }),
+                LinkAction::Write(frame(1)),
+            ]
+        );
+        assert!(link.poll(now).is_empty());
+        let later = now + Duration::from_millis(100);
+        assert_eq!(
+            link.poll(later),
+            vec![
+                LinkAction::Event(LinkEvent::GaveUp(1)),
+                LinkAction::Write(frame(2)),
+            ]
+        );
+
+        assert_eq!(
+            link.receive(&[ACK], later),
+            vec![LinkAction::Event(LinkEvent::Acked(2))]
+        );
+        assert!(link.is_idle());
+    }
+}
@@ -1,9 +1,10 @@
 use bytes::{BufMut, BytesMut};
+use std::fmt;
 
 use super::{
-    enums::MessageType,
-    footer::Footer,
-    header::Header,
+    enums::{DecodeError, FrameError, MessageType},
+    footer::{self, Footer, FOOTER_LEN},
+    header::{Header, HEADER_LEN},
     info::Info,
     messages::add_queue::{
         add_queue_request::AddQueueRequest, add_queue_response::AddQueueResponse,
@@ -12,13 +13,24 @@ use super::{
 };
 
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
     header: Header,
+    #[cfg_attr(feature = "serde", serde(rename = "payload"))]
     message_body: Payload,
+    // The CRC is computed by `encode` unless the message was decoded.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing, default = "Footer::new"))]
     footer: Footer,
+    // Set for decoded messages, whose length and CRC `encode` keeps as they
+    // were received, so that re-encoding gives back the same frame.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    received: bool,
 }
 
-impl_payload! { AddQueueRequest, AddQueueResponse }
+impl_payload! {
+    AddQueueRequest,
+    AddQueueResponse
+}
 
 impl Message {
     pub fn new(return_sequence_id: u16, instrument_id: u8, message_body: Payload) -> Self {
@@ -30,14 +42,30 @@ impl Message {
             ),
             message_body,
             footer: Footer::new(),
+            received: false,
         }
     }
 
-    fn set_sequence_id(&mut self, sequence_id: u16) {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.message_body
+    }
+
+    pub fn footer(&self) -> &Footer {
+        &self.footer
+    }
+
+    pub fn set_sequence_id(&mut self, sequence_id: u16) {
         self.header.sequence_id = sequence_id;
+        // The received CRC doesn't cover the new id.
+        self.received = false;
     }
 
-    fn is_my_response(&self, resp: &Message) -> bool {
+    // Returns true if `resp` answers this message.
+    pub fn is_my_response(&self, resp: &Message) -> bool {
         if let Some(message_type) = self.message_body.response_message_type() {
             return self.header.sequence_id == resp.header.return_sequence_id
                 && message_type == resp.message_body.get_message_type();
@@ -50,12 +78,40 @@ impl Message {
     }
 }
 
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} seq 0x{:04x} ret 0x{:04x} instrument {} length {} {}",
+            self.header.message_type,
+            self.header.sequence_id,
+            self.header.return_sequence_id,
+            self.header.instrument_id(),
+            self.header.message_length(),
+            self.message_body
+        )
+    }
+}
+
 impl Info for Message {
     fn encode(&mut self) -> Option<BytesMut> {
         let mut dst = BytesMut::with_capacity(0xFFFF);
 
+        let body = self.message_body.encode()?;
+        // The payload decides the type, whatever a deserialized header says.
+        self.header.message_type = self.message_body.get_message_type();
+        if !self.received {
+            // The length is assumed to cover the whole frame, from STX to
+            // ETX, see Header.
+            self.header
+                .set_message_length(u16::try_from(HEADER_LEN + body.len() + FOOTER_LEN).ok()?);
+        }
         dst.put(self.header.encode()?);
-        dst.put(self.message_body.encode()?);
+        dst.put(body);
+        if !self.received {
+            // The CRC covers everything after STX up to the footer.
+            self.footer.set_crc(footer::crc16(&dst[1..]));
+        }
         dst.put(self.footer.encode()?);
 
         Some(dst)
@@ -73,6 +129,8 @@ mod tests {
     };
 
     use super::*;
+    use crate::atellica::header::STX;
+    use bytes::Bytes;
 
     #[test]
     fn message2_test() {
@@ -122,4 +180,184 @@ mod tests {
         let msg_response_decoded = message_factory(&encode_response);
         println!("msg_response_decoded: {:?}", msg_response_decoded);
     }
+
+    #[test]
+    fn message_factory_test() {
+        let mut request = Message::new(
+            0,
+            10,
+            Payload::AddQueueRequest(AddQueueRequest::new(
+                InterfacePositionIndex::IP1,
+                &UrapTube::default(),
+            )),
+        );
+        request.set_sequence_id(0x1234);
+        let decoded = message_factory(&request.encode().unwrap()).unwrap();
+        assert_eq!(decoded.header().sequence_id, 0x1234);
+        assert_eq!(decoded.header().instrument_id(), 10);
+        assert_eq!(decoded.footer().crc(), request.footer().crc());
+        assert_ne!(decoded.footer().crc(), 0);
+        match decoded.payload() {
+            Payload::AddQueueRequest(payload) => {
+                assert_eq!(
+                    payload.interface_position_index(),
+                    InterfacePositionIndex::IP1
+                )
+            }
+            payload => panic!("unexpected payload {:?}", payload),
+        }
+
+        assert!(message_factory(&BytesMut::from(&b"not a frame"[..])).is_none());
+        assert!(message_factory(&BytesMut::from(&[STX][..])).is_none());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let tube = UrapTube::new(
+            CarrierOccupancy::UncappedTube,
+            TubeType::Greiner,
+            "S-0042".to_string(),
+            SamplePriority::STAT,
+            100,
+            130,
+        );
+        let ip = InterfacePositionIndex::IP1;
+        let payloads = [
+            Payload::AddQueueRequest(AddQueueRequest::new(ip, &tube)),
+            Payload::AddQueueResponse(AddQueueResponse::new(
+                ip,
+                "S-0042".to_string(),
+                AddQueueCommandStatusValues::Ok,
+            )),
+        ];
+        for payload in payloads {
+            let mut message = Message::new(7, 10, payload.clone());
+            let encoded = message.encode().unwrap();
+            assert_eq!(encoded.len(), message.header().message_length() as usize);
+
+            let decoded = message_factory(&encoded).unwrap();
+            assert_eq!(decoded.header().message_type, payload.get_message_type());
+            assert_eq!(decoded.header().return_sequence_id, 7);
+            assert_eq!(decoded.payload(), &payload);
+        }
+    }
+
+    #[test]
+    fn truncated_body_test() {
+        let mut message = Message::new(
+            0,
+            10,
+            Payload::AddQueueResponse(AddQueueResponse::new(
+                InterfacePositionIndex::IP0,
+                "S-0042".to_string(),
+                AddQueueCommandStatusValues::Ok,
+            )),
+        );
+        let mut encoded = message.encode().unwrap();
+        // Drop the command status, keeping the footer.
+        let footer = encoded.split_off(encoded.len() - FOOTER_LEN);
+        encoded.truncate(encoded.len() - 1);
+        encoded.unsplit(footer);
+        assert!(message_factory(&encoded).is_none());
+    }
+
+    #[test]
+    fn decode_frame_test() {
+        let mut message = Message::new(
+            0,
+            10,
+            Payload::AddQueueResponse(AddQueueResponse::new(
+                InterfacePositionIndex::IP0,
+                "S-0042".to_string(),
+                AddQueueCommandStatusValues::Ok,
+            )),
+        );
+        let encoded = message.encode().unwrap();
+        let len = encoded.len();
+
+        let mut bad_status = encoded.clone();
+        bad_status[len - FOOTER_LEN - 1] = 0x09;
+        assert_eq!(
+            decode_frame(&bad_status).unwrap_err(),
+            FrameError {
+                offset: len - FOOTER_LEN - 1,
+                error: DecodeError::InvalidField,
+            }
+        );
+
+        // The sample id length points past the status byte.
+        let mut bad_length = encoded.clone();
+        bad_length[HEADER_LEN + 1] = 8;
+        assert_eq!(
+            decode_frame(&bad_length).unwrap_err(),
+            FrameError {
+                offset: HEADER_LEN + 1,
+                error: DecodeError::FieldTooLong {
+                    length: 8,
+                    remaining: 7,
+                },
+            }
+        );
+
+        let mut no_etx = encoded.clone();
+        no_etx[len - 1] = 0;
+        assert_eq!(decode_frame(&no_etx).unwrap_err().offset, len - 1);
+    }
+
+    #[test]
+    fn unknown_test() {
+        let frame = BytesMut::from(
+            &[
+                STX, 0x00, 0x18, 0x00, 0x01, 0x00, 0x00, 0x99, 0x01, 1, 2, 3, 4, 5, 6, 7, 8, 10,
+                0xca, 0xfe, 0xab, 0x12, 0x34, 0x03,
+            ][..],
+        );
+        let mut message = message_factory(&frame).unwrap();
+        assert_eq!(
+            message.payload(),
+            &Payload::Unknown {
+                message_type: 0x9901,
+                body: Bytes::from_static(&[0xca, 0xfe, 0xab]),
+            }
+        );
+        assert_eq!(
+            message.to_string(),
+            "Unknown(0x9901) seq 0x0001 ret 0x0000 instrument 10 length 24 body ca fe ab"
+        );
+        assert_eq!(message.encode().unwrap(), frame);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        let mut message = Message::new(
+            7,
+            10,
+            Payload::AddQueueResponse(AddQueueResponse::new(
+                InterfacePositionIndex::IP1,
+                "S-0042".to_string(),
+                AddQueueCommandStatusValues::Ok,
+            )),
+        );
+        let encoded = message.encode().unwrap();
+        let json = serde_json::to_string(&message).unwrap();
+        let mut parsed: Message = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.payload(), message.payload());
+        assert_eq!(parsed.encode().unwrap(), encoded);
+
+        // Authored messages leave out what `encode` fills in.
+        let json = r#"{
+            "header": {"sequence_id": 1, "return_sequence_id": 0, "instrument_id": 10},
+            "payload": {"AddQueueResponse": {
+                "interface_position_index": "IP0",
+                "sample_id": "S-1",
+                "command_status": "Ok"
+            }}
+        }"#;
+        let mut authored: Message = serde_json::from_str(json).unwrap();
+        let decoded = message_factory(&authored.encode().unwrap()).unwrap();
+        assert_eq!(decoded.header().message_type, MessageType::AddQueueResponse);
+        assert_eq!(decoded.header().sequence_id, 1);
+        assert_eq!(decoded.payload(), authored.payload());
+    }
 }
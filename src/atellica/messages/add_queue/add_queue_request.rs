@@ -1,13 +1,14 @@
 use bytes::{BufMut, BytesMut};
-use message_parser_macros::MessageParsers;
 
 use crate::atellica::{
     enums::{DecodeError, InterfacePositionIndex, MessageType},
     info::Info,
     urap_tube::UrapTube,
+    utils::{body, get_enum},
 };
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AddQueueRequest {
     interface_position_index: InterfacePositionIndex,
     tube: UrapTube,
@@ -21,14 +22,22 @@ impl AddQueueRequest {
         }
     }
 
+    pub fn interface_position_index(&self) -> InterfacePositionIndex {
+        self.interface_position_index
+    }
+
+    pub fn tube(&self) -> &UrapTube {
+        &self.tube
+    }
+
     pub fn from_bytes(src: &BytesMut) -> Result<Self, DecodeError> {
-        let interface_position_index =
-            InterfacePositionIndex::try_from(src[18]).map_err(|_x| DecodeError::NoSTX)?;
+        Self::decode(&mut body(src)?)
+    }
 
-        let tube = UrapTube::default();
+    pub fn decode(src: &mut &[u8]) -> Result<Self, DecodeError> {
         Ok(Self {
-            interface_position_index,
-            tube: tube.clone(),
+            interface_position_index: get_enum(src)?,
+            tube: UrapTube::decode(src)?,
         })
     }
 }
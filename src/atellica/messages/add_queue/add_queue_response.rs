@@ -1,13 +1,13 @@
 use bytes::{BufMut, BytesMut};
-//use message_parser_macros::MessageParsers;
 
 use crate::atellica::{
     enums::{AddQueueCommandStatusValues, DecodeError, InterfacePositionIndex, MessageType},
     info::Info,
-    utils::sample_id_field_length,
+    utils::{body, get_enum, get_sample_id, sample_id_field_length},
 };
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AddQueueResponse {
     interface_position_index: InterfacePositionIndex,
     sample_id: String,
@@ -27,14 +27,27 @@ impl AddQueueResponse {
         }
     }
 
+    pub fn interface_position_index(&self) -> InterfacePositionIndex {
+        self.interface_position_index
+    }
+
+    pub fn sample_id(&self) -> &str {
+        &self.sample_id
+    }
+
+    pub fn command_status(&self) -> AddQueueCommandStatusValues {
+        self.command_status
+    }
+
     pub fn from_bytes(src: &BytesMut) -> Result<Self, DecodeError> {
-        let interface_position_index =
-            InterfacePositionIndex::try_from(src[18]).map_err(|_x| DecodeError::NoSTX)?;
-        let sample_id = String::from("");
+        Self::decode(&mut body(src)?)
+    }
+
+    pub fn decode(src: &mut &[u8]) -> Result<Self, DecodeError> {
         Ok(Self {
-            interface_position_index,
-            sample_id,
-            command_status: AddQueueCommandStatusValues::Ok,
+            interface_position_index: get_enum(src)?,
+            sample_id: get_sample_id(src)?,
+            command_status: get_enum(src)?,
         })
     }
 }
@@ -44,7 +57,7 @@ impl Info for AddQueueResponse {
         let mut dst = BytesMut::with_capacity(0xFFFF);
 
         dst.put_u8(self.interface_position_index as u8);
-        dst.put(sample_id_field_length(self.sample_id.as_str()));
+        dst.put(sample_id_field_length(&self.sample_id).ok()?);
         dst.put_u8(self.command_status as u8);
 
         Some(dst)
@@ -1,9 +1,26 @@
+pub mod builder;
 pub mod enums;
 pub mod footer;
 pub mod header;
 pub mod info;
+pub mod link;
 pub mod message;
 pub mod messages;
 pub mod payload;
+pub mod sequence;
 pub mod urap_tube;
 pub mod utils;
+
+pub use builder::{BuildError, MessageBuilder, PayloadBuilder, TubeBuilder};
+pub use enums::{DecodeError, FrameError};
+pub use footer::Footer;
+pub use header::Header;
+pub use info::Info;
+pub use link::{Link, LinkAction, LinkEvent, LinkSettings};
+pub use message::{decode_frame, message_factory, Message, Payload};
+pub use messages::add_queue::{
+    add_queue_request::AddQueueRequest, add_queue_response::AddQueueResponse,
+};
+pub use sequence::{SequenceAllocator, SequenceChecker, SequenceIssue};
+pub use urap_tube::{TubeError, UrapTube};
+pub use utils::{SampleIdError, SampleIdPolicy};
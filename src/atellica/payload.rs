@@ -1,48 +1,113 @@
-#[macro_use]
 #[macro_export]
 macro_rules! impl_payload {
     ($($var:ident),*) => {
-        #[derive(Debug, Default)]
-        enum Payload {
+        #[derive(Debug, Default, Clone, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum Payload {
             #[default]
             NoMessage,
             $(
             $var($var),
-        )*}
+            )*
+            /// A message of a type without a payload of its own; the body is
+            /// kept as it was received.
+            Unknown {
+                message_type: u16,
+                body: ::bytes::Bytes,
+            },
+        }
+
+        $(
+        impl From<$var> for Payload {
+            fn from(msg: $var) -> Self {
+                Payload::$var(msg)
+            }
+        }
+        )*
 
         impl Info for Payload {
             fn encode(&mut self) -> Option<BytesMut> {
                 match self {
                     $(Payload::$var(msg) => msg.encode(),)*
-                    _ => panic!("Nop"),
+                    Payload::Unknown { body, .. } => Some(BytesMut::from(&body[..])),
+                    Payload::NoMessage => None,
                 }
             }
 
             fn get_message_type(&self) -> MessageType {
                 match self {
                     $(Payload::$var(msg) => msg.get_message_type(),)*
-                    _ => panic!("Nop"),
+                    Payload::Unknown { message_type, .. } => MessageType::from(*message_type),
+                    Payload::NoMessage => MessageType::NoMessageType,
                 }
             }
 
             fn response_message_type(&self) -> Option<MessageType> {
                 match self {
                     $(Payload::$var(msg) => msg.response_message_type(),)*
-                    _ => panic!("Nop"),
+                    Payload::Unknown { .. } | Payload::NoMessage => None,
+                }
+            }
+        }
+
+        impl ::std::fmt::Display for Payload {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                match self {
+                    // The type is shown with the header, only the fields
+                    // are left here.
+                    $(Payload::$var(msg) => {
+                        let debug = format!("{:?}", msg);
+                        write!(f, "{}", debug.trim_start_matches(stringify!($var)).trim_start())
+                    })*
+                    Payload::Unknown { body, .. } => {
+                        write!(f, "body")?;
+                        for x in body.iter() {
+                            write!(f, " {:02x}", x)?;
+                        }
+                        Ok(())
+                    }
+                    Payload::NoMessage => write!(f, "no message"),
                 }
             }
         }
 
-        fn message_factory(src: &BytesMut) -> Option<Message> {
+        // Decodes a whole frame, from STX to ETX. Returns None if the frame
+        // is malformed. Types without a payload of their own are decoded
+        // as Payload::Unknown.
+        pub fn message_factory(src: &BytesMut) -> Option<Message> {
+            decode_frame(src).ok()
+        }
+
+        // Decodes a whole frame like message_factory, telling what is wrong
+        // with a malformed one and where. The CRC isn't checked.
+        pub fn decode_frame(src: &BytesMut) -> Result<Message, FrameError> {
+            use $crate::atellica::utils::{body, error_offset};
+
+            let at = |offset| move |error| FrameError { offset, error };
             let mut msg = Message::default();
-            msg.header.decode(src).ok()?;
+            msg.header.decode(src).map_err(|error| FrameError {
+                offset: if error == DecodeError::NoSTX { 0 } else { src.len() },
+                error,
+            })?;
+            let mut rest = body(src).map_err(at(src.len()))?;
             msg.message_body = match msg.header.message_type {
-                $(MessageType::$var => Payload::$var($var::from_bytes(src).expect("msg")),)*
-                _ => panic!("NoMessageType"),
+                $(MessageType::$var => Payload::$var(
+                    $var::decode(&mut rest).map_err(|error| FrameError {
+                        offset: error_offset(src, rest, error),
+                        error,
+                    })?,
+                ),)*
+                message_type => Payload::Unknown {
+                    message_type: message_type.into(),
+                    body: ::bytes::Bytes::copy_from_slice(rest),
+                },
             };
-            msg.footer.decode(src).ok()?;
+            msg.footer
+                .decode(src)
+                .map_err(at(src.len().saturating_sub(1)))?;
+            msg.received = true;
 
-            Some(msg)
+            Ok(msg)
         }
     }
 }
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{header::Header, message::Message};
+
+// Sequence ids run from 1 to 0xFFFF and wrap back to 1; 0 is the return
+// sequence id of messages that answer nothing.
+fn next_id(id: u16) -> u16 {
+    match id {
+        0xFFFF => 1,
+        id => id + 1,
+    }
+}
+
+// Number of steps from `from` to `to` going forward, 0 being skipped.
+fn distance(from: u16, to: u16) -> u16 {
+    let span = 0xFFFF_u32;
+    ((to as u32 + span - from as u32) % span) as u16
+}
+
+/// Hands out the sequence ids of the messages sent to each instrument
+#[derive(Debug, Default)]
+pub struct SequenceAllocator {
+    last: HashMap<u8, u16>,
+}
+
+impl SequenceAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the next sequence id for the instrument.
+    pub fn next(&mut self, instrument_id: u8) -> u16 {
+        let last = self.last.entry(instrument_id).or_insert(0);
+        *last = next_id(*last);
+        *last
+    }
+
+    // Gives the message the next sequence id for its instrument.
+    pub fn assign(&mut self, message: &mut Message) -> u16 {
+        let id = self.next(message.header().instrument_id());
+        message.set_sequence_id(id);
+        id
+    }
+}
+
+/// Something wrong with the sequence id of a received message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceIssue {
+    /// Messages were lost between the last one and this one.
+    Skipped {
+        expected: u16,
+        found: u16,
+        missing: u16,
+    },
+    /// The last message was received again.
+    Duplicated(u16),
+    /// The id belongs to a message that should have come earlier.
+    OutOfOrder { expected: u16, found: u16 },
+}
+
+impl fmt::Display for SequenceIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceIssue::Skipped {
+                expected,
+                found,
+                missing,
+            } => write!(
+                f,
+                "skipped {} (expected 0x{:04x}, found 0x{:04x})",
+                missing, expected, found
+            ),
+            SequenceIssue::Duplicated(id) => write!(f, "duplicated 0x{:04x}", id),
+            SequenceIssue::OutOfOrder { expected, found } => write!(
+                f,
+                "out of order (expected 0x{:04x}, found 0x{:04x})",
+                expected, found
+            ),
+        }
+    }
+}
+
+/// Follows the sequence ids received from each instrument, in one direction
+#[derive(Debug, Default)]
+pub struct SequenceChecker {
+    last: HashMap<u8, u16>,
+}
+
+impl SequenceChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Checks the sequence id of a received header against the previous one
+    // from the same instrument.
+    pub fn check(&mut self, header: &Header) -> Option<SequenceIssue> {
+        let found = header.sequence_id;
+        // The first message from an instrument starts its sequence.
+        let last = self.last.insert(header.instrument_id(), found)?;
+        let expected = next_id(last);
+        if found == expected {
+            return None;
+        }
+        let issue = if found == last {
+            SequenceIssue::Duplicated(found)
+        } else {
+            match distance(expected, found) {
+                missing @ 1..0x8000 => {
+                    return Some(SequenceIssue::Skipped {
+                        expected,
+                        found,
+                        missing,
+                    })
+                }
+                _ => SequenceIssue::OutOfOrder { expected, found },
+            }
+        };
+        // A late or repeated message doesn't move the sequence back.
+        self.last.insert(header.instrument_id(), last);
+        Some(issue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atellica::enums::{
+        AddQueueCommandStatusValues, InterfacePositionIndex, MessageType,
+    };
+    use crate::atellica::{message::Payload, AddQueueResponse};
+
+    fn header(instrument_id: u8, sequence_id: u16) -> Header {
+        let response = AddQueueResponse::new(
+            InterfacePositionIndex::IP0,
+            "S-1".to_string(),
+            AddQueueCommandStatusValues::Ok,
+        );
+        let mut message = Message::new(0, instrument_id, Payload::AddQueueResponse(response));
+        message.set_sequence_id(sequence_id);
+        assert_eq!(message.header().message_type, MessageType::AddQueueResponse);
+        *message.header()
+    }
+
+    #[test]
+    fn allocator_test() {
+        let mut allocator = SequenceAllocator::new();
+        assert_eq!(allocator.next(1), 1);
+        assert_eq!(allocator.next(1), 2);
+        assert_eq!(allocator.next(2), 1);
+
+        allocator.last.insert(1, 0xFFFE);
+        assert_eq!(allocator.next(1), 0xFFFF);
+        assert_eq!(allocator.next(1), 1);
+    }
+
+    #[test]
+    fn checker_test() {
+        let mut checker = SequenceChecker::new();
+        let ids = [
+            (1, 0xFFFE),
+            (1, 0xFFFF),
+            (1, 1),
+            (2, 7),
+            (1, 1),
+            (1, 4),
+            (1, 2),
+            (1, 5),
+        ];
+        let issues: Vec<_> = ids
+            .iter()
+            .map(|(instrument, id)| checker.check(&header(*instrument, *id)))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                None,
+                None,
+                None,
+                None,
+                Some(SequenceIssue::Duplicated(1)),
+                Some(SequenceIssue::Skipped {
+                    expected: 2,
+                    found: 4,
+                    missing: 2
+                }),
+                Some(SequenceIssue::OutOfOrder {
+                    expected: 5,
+                    found: 2
+                }),
+                None,
+            ]
+        );
+    }
+}
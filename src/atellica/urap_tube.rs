@@ -1,11 +1,55 @@
 use bytes::{BufMut, BytesMut};
+use std::fmt;
 
 use super::{
-    enums::{CarrierOccupancy, SamplePriority, TubeType},
-    utils::sample_id_field_length,
+    enums::{CarrierOccupancy, DecodeError, SamplePriority, TubeType},
+    utils::{
+        check_sample_id, get_sample_id, get_u8, sample_id_field_length, SampleIdError,
+        SampleIdPolicy,
+    },
 };
 
-#[derive(Debug, Clone, Default)]
+// The height is sent in millimetres and the diameter in tenths of a
+// millimetre, one byte each. Both scales are assumed, they fit the usual
+// tube sizes (up to 255 mm high, 25.5 mm wide) in a byte.
+const HEIGHT_STEPS_PER_MM: f32 = 1.0;
+const DIAMETER_STEPS_PER_MM: f32 = 10.0;
+
+/// Why a tube can't be sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum TubeError {
+    /// The sample id is too long or holds an invalid character.
+    SampleId(SampleIdError),
+    /// The height, in mm, is negative or above 255 mm.
+    HeightOutOfRange(f32),
+    /// The diameter, in mm, is negative or above 25.5 mm.
+    DiameterOutOfRange(f32),
+}
+
+impl fmt::Display for TubeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TubeError::SampleId(err) => write!(f, "{}", err),
+            TubeError::HeightOutOfRange(mm) => write!(f, "tube height {} mm is out of range", mm),
+            TubeError::DiameterOutOfRange(mm) => {
+                write!(f, "tube diameter {} mm is out of range", mm)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TubeError {}
+
+// Converts millimetres to the steps sent on the wire, if they fit in a byte.
+fn to_steps(mm: f32, steps_per_mm: f32) -> Option<u8> {
+    let steps = (mm * steps_per_mm).round();
+    (0.0..=u8::MAX as f32)
+        .contains(&steps)
+        .then_some(steps as u8)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UrapTube {
     carrier_occupancy: CarrierOccupancy,
     tube_type: TubeType,
@@ -34,16 +78,135 @@ impl UrapTube {
         }
     }
 
+    pub fn carrier_occupancy(&self) -> CarrierOccupancy {
+        self.carrier_occupancy
+    }
+
+    pub fn tube_type(&self) -> TubeType {
+        self.tube_type
+    }
+
+    pub fn sample_priority(&self) -> SamplePriority {
+        self.sample_priority
+    }
+
+    // Returns the height as sent, in millimetres.
+    pub fn tube_height(&self) -> u8 {
+        self.tube_height
+    }
+
+    // Returns the diameter as sent, in tenths of a millimetre.
+    pub fn tube_diameter(&self) -> u8 {
+        self.tube_diameter
+    }
+
+    pub fn tube_height_mm(&self) -> f32 {
+        self.tube_height as f32 / HEIGHT_STEPS_PER_MM
+    }
+
+    pub fn tube_diameter_mm(&self) -> f32 {
+        self.tube_diameter as f32 / DIAMETER_STEPS_PER_MM
+    }
+
+    // Sets the height, rounded to the nearest millimetre.
+    pub fn set_tube_height_mm(&mut self, mm: f32) -> Result<(), TubeError> {
+        self.tube_height =
+            to_steps(mm, HEIGHT_STEPS_PER_MM).ok_or(TubeError::HeightOutOfRange(mm))?;
+        Ok(())
+    }
+
+    // Sets the diameter, rounded to the nearest tenth of a millimetre.
+    pub fn set_tube_diameter_mm(&mut self, mm: f32) -> Result<(), TubeError> {
+        self.tube_diameter =
+            to_steps(mm, DIAMETER_STEPS_PER_MM).ok_or(TubeError::DiameterOutOfRange(mm))?;
+        Ok(())
+    }
+
+    // Checks that the sample id can be sent as it is.
+    pub fn validate(&self) -> Result<(), TubeError> {
+        check_sample_id(&self.sample_id, SampleIdPolicy::Reject).map_err(TubeError::SampleId)?;
+        Ok(())
+    }
+
+    // Returns None if the tube doesn't pass `validate`.
     pub fn encode(&mut self) -> Option<BytesMut> {
+        self.validate().ok()?;
         let mut dst = BytesMut::with_capacity(0xFFFF);
 
-        dst.put_u8(self.carrier_occupancy as u8);
-        dst.put_u8(self.tube_type as u8);
-        dst.put(sample_id_field_length(self.sample_id.as_str()));
-        dst.put_u8(self.sample_priority as u8);
-        dst.put_u8(self.tube_height as u8);
-        dst.put_u8(self.tube_diameter as u8);
+        dst.put_u8(self.carrier_occupancy.into());
+        dst.put_u8(self.tube_type.into());
+        dst.put(sample_id_field_length(&self.sample_id).ok()?);
+        dst.put_u8(self.sample_priority.into());
+        dst.put_u8(self.tube_height);
+        dst.put_u8(self.tube_diameter);
 
         Some(dst)
     }
+
+    pub fn decode(src: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self {
+            carrier_occupancy: get_u8(src)?.into(),
+            tube_type: get_u8(src)?.into(),
+            sample_id: get_sample_id(src)?,
+            sample_priority: get_u8(src)?.into(),
+            tube_height: get_u8(src)?,
+            tube_diameter: get_u8(src)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimensions_test() {
+        let mut tube = UrapTube::default();
+        tube.set_tube_height_mm(100.0).unwrap();
+        tube.set_tube_diameter_mm(13.04).unwrap();
+        assert_eq!((tube.tube_height(), tube.tube_diameter()), (100, 130));
+        assert_eq!(tube.tube_diameter_mm(), 13.0);
+
+        assert_eq!(
+            tube.set_tube_height_mm(300.0),
+            Err(TubeError::HeightOutOfRange(300.0))
+        );
+        assert_eq!(
+            tube.set_tube_diameter_mm(-1.0),
+            Err(TubeError::DiameterOutOfRange(-1.0))
+        );
+        assert_eq!(tube.tube_height(), 100);
+    }
+
+    #[test]
+    fn validate_test() {
+        let mut tube = UrapTube {
+            sample_id: "S 0042".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(tube.validate(), Ok(()));
+
+        tube.sample_id = "x".repeat(256);
+        assert_eq!(
+            tube.validate(),
+            Err(TubeError::SampleId(SampleIdError::TooLong(256)))
+        );
+        assert!(tube.encode().is_none());
+
+        tube.sample_id = "S\t42".to_string();
+        assert_eq!(
+            tube.validate(),
+            Err(TubeError::SampleId(SampleIdError::InvalidChar('\t')))
+        );
+    }
+
+    #[test]
+    fn unknown_values_test() {
+        let mut src = &[7, 42, 0, 9, 80, 130][..];
+        let tube = UrapTube::decode(&mut src).unwrap();
+        assert_eq!(tube.carrier_occupancy(), CarrierOccupancy::Unknown(7));
+        assert_eq!(tube.tube_type(), TubeType::Unknown(42));
+        assert_eq!(tube.sample_priority(), SamplePriority::Unknown(9));
+        assert_eq!(tube.tube_diameter_mm(), 13.0);
+    }
 }
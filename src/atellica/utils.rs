@@ -1,10 +1,179 @@
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::fmt;
 
-pub fn sample_id_field_length(sample_id: &str) -> BytesMut {
+use super::{enums::DecodeError, footer::FOOTER_LEN, header::HEADER_LEN};
+
+// Longest sample id that fits behind its one byte length.
+pub const SAMPLE_ID_MAX_LEN: usize = u8::MAX as usize;
+
+/// What to do with a sample id longer than SAMPLE_ID_MAX_LEN characters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleIdPolicy {
+    /// Refuse the sample id.
+    #[default]
+    Reject,
+    /// Keep its first SAMPLE_ID_MAX_LEN characters.
+    Truncate,
+}
+
+/// Why a sample id can't be sent
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SampleIdError {
+    /// The sample id has more characters than its length byte can count.
+    TooLong(usize),
+    /// The character is a control character or outside ISO 8859-1.
+    InvalidChar(char),
+}
+
+impl fmt::Display for SampleIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleIdError::TooLong(len) => write!(
+                f,
+                "sample id is {} characters long (at most {})",
+                len, SAMPLE_ID_MAX_LEN
+            ),
+            SampleIdError::InvalidChar(c) => {
+                write!(f, "sample id holds invalid character {:?}", c)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SampleIdError {}
+
+// Sample ids are sent in ISO 8859-1, one byte per character, without
+// control characters.
+fn latin1(c: char) -> Option<u8> {
+    u8::try_from(c as u32)
+        .ok()
+        .filter(|x| matches!(x, 0x20..=0x7E | 0xA0..=0xFF))
+}
+
+// Returns the sample id to send, after checking its characters and applying
+// the policy to its length.
+pub fn check_sample_id(sample_id: &str, policy: SampleIdPolicy) -> Result<&str, SampleIdError> {
+    if let Some(c) = sample_id.chars().find(|c| latin1(*c).is_none()) {
+        return Err(SampleIdError::InvalidChar(c));
+    }
+    match sample_id.char_indices().nth(SAMPLE_ID_MAX_LEN) {
+        None => Ok(sample_id),
+        Some((end, _)) if policy == SampleIdPolicy::Truncate => Ok(&sample_id[..end]),
+        Some(_) => Err(SampleIdError::TooLong(sample_id.chars().count())),
+    }
+}
+
+// Writes the sample id behind its length byte. Sample ids that don't fit
+// are rejected; apply a policy with check_sample_id first to truncate them.
+pub fn sample_id_field_length(sample_id: &str) -> Result<BytesMut, SampleIdError> {
+    let sample_id = check_sample_id(sample_id, SampleIdPolicy::Reject)?;
     let mut dst = BytesMut::with_capacity(sample_id.len() + 1);
 
-    dst.put_u8(sample_id.len() as u8);
-    dst.put(sample_id.as_bytes());
+    dst.put_u8(sample_id.chars().count() as u8);
+    dst.extend(sample_id.chars().filter_map(latin1));
+
+    Ok(dst)
+}
+
+// Returns the message body of a whole frame, between the header and the
+// footer.
+pub fn body(src: &BytesMut) -> Result<&[u8], DecodeError> {
+    src.get(HEADER_LEN..src.len().saturating_sub(FOOTER_LEN))
+        .ok_or(DecodeError::TooShort)
+}
+
+// Returns where a body decoder failed, from what it left of the body: the
+// byte it just read for an invalid value or length, or the one it didn't
+// get when the body ended too soon.
+pub fn error_offset(src: &BytesMut, rest: &[u8], error: DecodeError) -> usize {
+    let next = src.len().saturating_sub(FOOTER_LEN + rest.len());
+    match error {
+        DecodeError::TooShort => next,
+        _ => next.saturating_sub(1),
+    }
+}
+
+pub fn get_u8(src: &mut &[u8]) -> Result<u8, DecodeError> {
+    if src.remaining() < 1 {
+        return Err(DecodeError::TooShort);
+    }
+    Ok(src.get_u8())
+}
+
+pub fn get_u16(src: &mut &[u8]) -> Result<u16, DecodeError> {
+    if src.remaining() < 2 {
+        return Err(DecodeError::TooShort);
+    }
+    Ok(src.get_u16())
+}
+
+// Reads a one byte field holding one of the values of `T`.
+pub fn get_enum<T>(src: &mut &[u8]) -> Result<T, DecodeError>
+where
+    T: TryFromPrimitive<Primitive = u8>,
+{
+    T::try_from_primitive(get_u8(src)?).map_err(|_x| DecodeError::InvalidField)
+}
+
+// Reads a field written by sample_id_field_length. `src` holds the rest of
+// the body, so a length running past it means the frame is malformed.
+pub fn get_sample_id(src: &mut &[u8]) -> Result<String, DecodeError> {
+    let len = get_u8(src)? as usize;
+    if src.remaining() < len {
+        return Err(DecodeError::FieldTooLong {
+            length: len,
+            remaining: src.remaining(),
+        });
+    }
+    let sample_id = src[..len].iter().map(|x| *x as char).collect();
+    src.advance(len);
+    Ok(sample_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_id_test() {
+        assert_eq!(
+            sample_id_field_length("Ab\u{e9}").unwrap(),
+            &[3, b'A', b'b', 0xE9][..]
+        );
+        assert_eq!(
+            sample_id_field_length("A\u{20ac}"),
+            Err(SampleIdError::InvalidChar('\u{20ac}'))
+        );
+        assert_eq!(
+            sample_id_field_length("A\n"),
+            Err(SampleIdError::InvalidChar('\n'))
+        );
+
+        let long = "\u{e9}".repeat(300);
+        assert_eq!(
+            sample_id_field_length(&long),
+            Err(SampleIdError::TooLong(300))
+        );
+        let truncated = check_sample_id(&long, SampleIdPolicy::Truncate).unwrap();
+        assert_eq!(truncated.chars().count(), SAMPLE_ID_MAX_LEN);
+        let field = sample_id_field_length(truncated).unwrap();
+        assert_eq!(field.len(), SAMPLE_ID_MAX_LEN + 1);
+
+        let mut src = &field[..];
+        assert_eq!(get_sample_id(&mut src).unwrap(), truncated);
+        assert!(src.is_empty());
+    }
 
-    dst
+    #[test]
+    fn get_sample_id_test() {
+        let mut src = &[5, b'S', b'1'][..];
+        assert_eq!(
+            get_sample_id(&mut src),
+            Err(DecodeError::FieldTooLong {
+                length: 5,
+                remaining: 2
+            })
+        );
+    }
 }
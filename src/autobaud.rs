@@ -0,0 +1,181 @@
+use bytes::BytesMut;
+use serial_monitor::atellica::enums::MessageType;
+use serial_monitor::atellica::header::{Header, HEADER_LEN, STX};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::time::{self, Instant};
+
+use crate::error::ProgramError;
+use crate::interface::serial;
+use crate::opt::{Opt, ParityOpt};
+
+const BAUD_RATES: [u32; 9] = [1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400];
+
+const COMBOS: [(usize, ParityOpt); 5] = [
+    (8, ParityOpt::None),
+    (7, ParityOpt::Even),
+    (7, ParityOpt::Odd),
+    (8, ParityOpt::Even),
+    (8, ParityOpt::Odd),
+];
+
+// Serial settings tried while looking for the baud rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candidate {
+    baud: u32,
+    databits: usize,
+    parity: ParityOpt,
+}
+
+impl Candidate {
+    pub fn apply(&self, opt: &mut Opt) {
+        opt.baud = self.baud;
+        opt.databits = self.databits;
+        opt.parity = self.parity;
+    }
+
+    // Describes the settings the usual way, e.g. "9600 8N1".
+    fn describe(&self, stopbits: usize) -> String {
+        let parity = match self.parity {
+            ParityOpt::None => 'N',
+            ParityOpt::Odd => 'O',
+            ParityOpt::Even => 'E',
+        };
+        format!("{} {}{}{}", self.baud, self.databits, parity, stopbits)
+    }
+}
+
+// How the data received with a candidate looks.
+#[derive(Debug, Default, PartialEq)]
+struct Score {
+    bytes: usize,
+    printable: usize,
+    suspect: usize,
+    headers: usize,
+}
+
+// Bytes expected in text and framed protocols.
+fn is_printable(x: u8) -> bool {
+    matches!(x, 0x20..=0x7E | b'\t' | b'\r' | b'\n' | 0x02..=0x06 | 0x15)
+}
+
+// Bytes that are most often the result of a framing error: a wrong baud
+// rate mostly reads breaks (0x00), idle line (0xFF) or a set high bit.
+fn is_suspect(x: u8, databits: usize) -> bool {
+    x == 0x00 || x == 0xFF || (databits < 8 && x >= 0x80)
+}
+
+impl Score {
+    fn new(data: &[u8], databits: usize) -> Self {
+        let headers = data
+            .windows(HEADER_LEN)
+            .filter(|window| window[0] == STX)
+            .filter(|window| {
+                // Only known message types tell a header from line noise.
+                let mut header = Header::default();
+                header.decode(&BytesMut::from(*window)).is_ok()
+                    && !matches!(
+                        header.message_type,
+                        MessageType::NoMessageType | MessageType::Unknown(_)
+                    )
+            })
+            .count();
+        Self {
+            bytes: data.len(),
+            printable: data.iter().filter(|x| is_printable(**x)).count(),
+            suspect: data.iter().filter(|x| is_suspect(**x, databits)).count(),
+            headers,
+        }
+    }
+
+    // Each Atellica header weighs as much as a whole clean buffer.
+    fn value(&self) -> f64 {
+        if self.bytes == 0 {
+            return 0.0;
+        }
+        (self.printable as f64 - self.suspect as f64) / self.bytes as f64 + self.headers as f64
+    }
+}
+
+// Reads whatever the port receives during the window.
+async fn listen(path: &str, opt: &Opt) -> Result<Vec<u8>, ProgramError> {
+    let mut port = serial::open(path, opt)?;
+    let deadline = Instant::now() + Duration::from_millis(opt.auto_baud_ms);
+    let mut data = Vec::new();
+    let mut buf = [0u8; 1024];
+    while let Ok(read) = time::timeout_at(deadline, port.read(&mut buf)).await {
+        match read.map_err(ProgramError::IoError)? {
+            0 => break,
+            count => data.extend_from_slice(&buf[..count]),
+        }
+    }
+    Ok(data)
+}
+
+// Listens to the port with every candidate setting, prints how each one
+// scored and returns the best one, if any data was received at all.
+pub async fn detect(path: &str, opt: &Opt) -> Result<Option<Candidate>, ProgramError> {
+    let combos = if opt.auto_baud_combos {
+        &COMBOS[..]
+    } else {
+        &COMBOS[..1]
+    };
+
+    let mut best: Option<(Candidate, f64)> = None;
+    for baud in BAUD_RATES {
+        for (databits, parity) in combos {
+            let candidate = Candidate {
+                baud,
+                databits: *databits,
+                parity: *parity,
+            };
+            let mut candidate_opt = opt.clone();
+            candidate.apply(&mut candidate_opt);
+
+            let score = Score::new(&listen(path, &candidate_opt).await?, *databits);
+            let value = score.value();
+            println!(
+                "{} | {} | {} bytes, {} printable, {} suspect, {} Atellica headers | score {:.3}",
+                path,
+                candidate.describe(opt.stopbits),
+                score.bytes,
+                score.printable,
+                score.suspect,
+                score.headers,
+                value
+            );
+            if score.bytes > 0 && best.is_none_or(|(_, best)| value > best) {
+                best = Some((candidate, value));
+            }
+        }
+    }
+
+    match best {
+        Some((candidate, _)) => {
+            println!("{} | best: {}", path, candidate.describe(opt.stopbits))
+        }
+        None => println!("{} | no data received", path),
+    }
+    Ok(best.map(|(candidate, _)| candidate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_test() {
+        let text = Score::new(b"OK\r\n", 8);
+        assert_eq!(text.printable, 4);
+        assert_eq!(text.suspect, 0);
+
+        let noise = Score::new(&[0x00, 0xFF, 0xE0, 0x41], 8);
+        assert_eq!(noise.suspect, 2);
+        assert!(noise.value() < text.value());
+
+        let mut frame = vec![STX, 0, 0, 0x12, 0x34, 0, 0, 0x04, 0x05];
+        frame.extend_from_slice(&[0; 8]);
+        frame.push(10);
+        assert_eq!(Score::new(&frame, 8).headers, 1);
+    }
+}
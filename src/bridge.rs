@@ -0,0 +1,342 @@
+use bytes::{Bytes, BytesMut};
+use std::future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::{self, Instant, Interval};
+use tokio_serial::SerialStream;
+use tokio_util::codec::Decoder;
+
+use crate::codec::Codec;
+use crate::error::ProgramError;
+use crate::interface::serial;
+use crate::modem::ModemLines;
+use crate::opt::{Opt, ServeOpt, WritersOpt};
+use crate::rfc2217::{self, Command, Lines, Request, Session};
+
+// What the TCP clients send to the task owning the port.
+enum ClientEvent {
+    Data(usize, Bytes),
+    // An RFC 2217 command, answered with the setting now in use.
+    Control(usize, Command, oneshot::Sender<Option<Command>>),
+    Closed(usize),
+}
+
+// Decides which clients may write to the port.
+struct Writers {
+    mode: WritersOpt,
+    // The client allowed to write when only a single writer is allowed.
+    owner: Option<usize>,
+}
+
+impl Writers {
+    fn allows(&mut self, id: usize) -> bool {
+        match self.mode {
+            WritersOpt::None => false,
+            WritersOpt::Single => *self.owner.get_or_insert(id) == id,
+            WritersOpt::Multi => true,
+        }
+    }
+
+    fn release(&mut self, id: usize) {
+        if self.owner == Some(id) {
+            self.owner = None;
+        }
+    }
+}
+
+// Relays a port to any number of TCP clients. Everything the port receives
+// is sent to every client and displayed; what clients send is written to
+// the port, as allowed by --writers, and displayed too. With --rfc2217 the
+// clients speak Telnet and may change the port settings and control lines.
+// Returns the code to exit with once a trigger match stopped it, and an
+// error once the port can no longer be served.
+pub async fn serve(path: &str, opt: &Opt, serve: &ServeOpt) -> Result<Option<i32>, ProgramError> {
+    let port = serial::open(path, opt)?;
+    let listener = TcpListener::bind(serve.listen)
+        .await
+        .map_err(ProgramError::IoError)?;
+
+    let (to_clients, _) = broadcast::channel(256);
+    let (modem_sender, modem_lines) = watch::channel(ModemLines::default());
+    let (from_clients, client_events) = mpsc::channel(256);
+    let owner = PortOwner {
+        port,
+        rx: Codec::new(path.to_string(), opt),
+        tx: Codec::new(path.to_string(), opt).with_direction("tx"),
+        writers: Writers {
+            mode: serve.writers,
+            owner: None,
+        },
+        lines: Lines::default(),
+        modem_poll: serve
+            .rfc2217
+            .then(|| time::interval(Duration::from_millis(opt.modem_poll_ms))),
+        modem_sender,
+    };
+    let mut owner = tokio::task::spawn(owner.run(to_clients.clone(), client_events));
+
+    let mut events = Codec::new(path.to_string(), opt);
+    let protocol = if serve.rfc2217 { "RFC 2217" } else { "TCP" };
+    events.event(&format!("listening for {} on {}", protocol, serve.listen));
+    for id in 1.. {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted.map_err(ProgramError::IoError)?,
+            code = &mut owner => return match code {
+                Ok(Some(code)) => Ok(Some(code)),
+                Ok(None) => Err(ProgramError::IoError(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{} was closed", path),
+                ))),
+                Err(err) => Err(ProgramError::IoError(io::Error::other(err))),
+            },
+        };
+        events.event(&format!("client {} connected from {}", id, addr));
+        let session = serve.rfc2217.then(|| Session::new(*modem_lines.borrow()));
+        tokio::task::spawn(handle_client(
+            id,
+            stream,
+            addr,
+            session,
+            to_clients.subscribe(),
+            modem_lines.clone(),
+            from_clients.clone(),
+        ));
+    }
+    Ok(None)
+}
+
+// Owns the port, so that clients can change its settings while it is read
+// and written.
+struct PortOwner {
+    port: SerialStream,
+    rx: Codec,
+    tx: Codec,
+    writers: Writers,
+    lines: Lines,
+    modem_poll: Option<Interval>,
+    modem_sender: watch::Sender<ModemLines>,
+}
+
+impl PortOwner {
+    // Runs until the port is closed, or until a trigger match stops it.
+    // Returns the code to exit with in that case.
+    async fn run(
+        mut self,
+        to_clients: broadcast::Sender<Bytes>,
+        mut client_events: mpsc::Receiver<ClientEvent>,
+    ) -> Option<i32> {
+        let mut rx_display = BytesMut::new();
+        let mut tx_display = BytesMut::new();
+        let mut buf = [0u8; 1024];
+        while self.rx.exit_code().is_none() && self.tx.exit_code().is_none() {
+            let rx_idle = self.rx.idle_deadline();
+            let tx_idle = self.tx.idle_deadline();
+            tokio::select! {
+                read = self.port.read(&mut buf) => match read {
+                    Ok(0) => break,
+                    Ok(count) => {
+                        // No client connected is not an error.
+                        let _ = to_clients.send(Bytes::copy_from_slice(&buf[..count]));
+                        rx_display.extend_from_slice(&buf[..count]);
+                        let _ = self.rx.decode(&mut rx_display);
+                    }
+                    Err(err) => {
+                        self.rx.error(&err);
+                        break;
+                    }
+                },
+                event = client_events.recv() => match event {
+                    Some(ClientEvent::Data(id, data)) => {
+                        if !self.writers.allows(id) {
+                            self.tx.event(&format!(
+                                "dropped {} bytes from read-only client {}",
+                                data.len(),
+                                id
+                            ));
+                            continue;
+                        }
+                        if let Err(err) = self.port.write_all(&data).await {
+                            self.tx.error(&err);
+                            continue;
+                        }
+                        tx_display.extend_from_slice(&data);
+                        let _ = self.tx.decode(&mut tx_display);
+                    }
+                    Some(ClientEvent::Control(id, command, reply)) => {
+                        let _ = reply.send(self.control(id, command));
+                    }
+                    Some(ClientEvent::Closed(id)) => {
+                        self.writers.release(id);
+                        self.tx.event(&format!("client {} disconnected", id));
+                    }
+                    None => break,
+                },
+                _ = Self::sleep_until(rx_idle) => self.rx.flush(&mut rx_display),
+                _ = Self::sleep_until(tx_idle) => self.tx.flush(&mut tx_display),
+                _ = Self::tick(&mut self.modem_poll) => self.poll_modem_lines(),
+            }
+        }
+        self.rx.exit_code().or(self.tx.exit_code())
+    }
+
+    // Applies an RFC 2217 command. Clients that may not write only get to
+    // see the current settings.
+    fn control(&mut self, id: usize, command: Command) -> Option<Command> {
+        let query = command.query();
+        let command = if command == query || self.writers.allows(id) {
+            command
+        } else {
+            query
+        };
+        match self.lines.apply(&mut self.port, &command) {
+            Ok(reply) => {
+                if command != command.query() {
+                    if let Some(reply) = &reply {
+                        self.tx.event(&format!("client {} set {}", id, reply));
+                    }
+                }
+                reply
+            }
+            Err(err) => {
+                self.tx.error(&err.into());
+                None
+            }
+        }
+    }
+
+    async fn sleep_until(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => time::sleep_until(deadline).await,
+            None => future::pending().await,
+        }
+    }
+
+    async fn tick(interval: &mut Option<Interval>) {
+        match interval {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => future::pending().await,
+        }
+    }
+
+    fn poll_modem_lines(&mut self) {
+        match ModemLines::read(&mut self.port) {
+            Ok(lines) => {
+                self.modem_sender.send_if_modified(|current| {
+                    let modified = *current != lines;
+                    *current = lines;
+                    modified
+                });
+            }
+            Err(err) => {
+                // Ports without modem lines are still worth serving.
+                self.modem_poll = None;
+                self.rx.error(&err.into());
+            }
+        }
+    }
+}
+
+async fn handle_client(
+    id: usize,
+    stream: TcpStream,
+    addr: SocketAddr,
+    mut session: Option<Session>,
+    mut to_client: broadcast::Receiver<Bytes>,
+    mut modem_lines: watch::Receiver<ModemLines>,
+    from_client: mpsc::Sender<ClientEvent>,
+) {
+    let (mut reader, mut writer) = stream.into_split();
+    if let Some(session) = &mut session {
+        if writer.write_all(&session.start()).await.is_err() {
+            return;
+        }
+    }
+    let mut buf = [0u8; 1024];
+    loop {
+        tokio::select! {
+            data = to_client.recv() => match data {
+                Ok(data) => {
+                    let data = match &session {
+                        Some(session) if session.suspended => continue,
+                        Some(_) => rfc2217::escape(&data),
+                        None => data.to_vec(),
+                    };
+                    if writer.write_all(&data).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    eprintln!("client {} ({}) is too slow, {} chunks lost", id, addr, count);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            Ok(()) = modem_lines.changed(), if session.is_some() => {
+                let lines = *modem_lines.borrow_and_update();
+                let notify = session.as_mut().and_then(|session| session.modem_state(lines));
+                if let Some(notify) = notify {
+                    if writer.write_all(&notify).await.is_err() {
+                        break;
+                    }
+                }
+            },
+            read = reader.read(&mut buf) => match read {
+                Ok(0) | Err(_) => break,
+                Ok(count) => {
+                    let requests = match &mut session {
+                        Some(session) => session.receive(&buf[..count]),
+                        None => vec![Request::Data(buf[..count].to_vec())],
+                    };
+                    if !forward(id, requests, &mut writer, &from_client).await {
+                        break;
+                    }
+                }
+            },
+        }
+    }
+    let _ = from_client.send(ClientEvent::Closed(id)).await;
+}
+
+// Passes what a client asked for to the port and answers it. Returns false
+// once the client or the port is gone.
+async fn forward(
+    id: usize,
+    requests: Vec<Request>,
+    writer: &mut OwnedWriteHalf,
+    from_client: &mpsc::Sender<ClientEvent>,
+) -> bool {
+    for request in requests {
+        let reply = match request {
+            Request::Data(data) => {
+                let event = ClientEvent::Data(id, Bytes::from(data));
+                if from_client.send(event).await.is_err() {
+                    return false;
+                }
+                continue;
+            }
+            Request::Command(command) => {
+                let (sender, receiver) = oneshot::channel();
+                let event = ClientEvent::Control(id, command, sender);
+                if from_client.send(event).await.is_err() {
+                    return false;
+                }
+                match receiver.await {
+                    Ok(Some(reply)) => reply.encode(true),
+                    Ok(None) => continue,
+                    Err(_) => return false,
+                }
+            }
+            Request::Reply(reply) => reply,
+        };
+        if writer.write_all(&reply).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
@@ -1,51 +1,450 @@
-use bytes::BytesMut;
-use chrono::{DateTime, Local};
+use bytes::{Bytes, BytesMut};
+use serial_monitor::atellica::{self, Header, Link, LinkAction, LinkSettings, SequenceChecker};
+use std::ops::Range;
+use std::time::{Duration, Instant};
 use std::{ascii, io};
+use tokio::time;
 use tokio_util::codec::Decoder;
 
+use crate::color;
+use crate::framing::FrameOpt;
 use crate::opt::{CodecOpt, Opt};
+use crate::timestamp::Timestamp;
+use crate::trigger::Triggers;
+
+const DUMP_ROW_LEN: usize = 16;
+
+const CONTROL_NAMES: [&str; 32] = [
+    "NUL", "SOH", "STX", "ETX", "EOT", "ENQ", "ACK", "BEL", "BS", "HT", "LF", "VT", "FF", "CR",
+    "SO", "SI", "DLE", "DC1", "DC2", "DC3", "DC4", "NAK", "SYN", "ETB", "CAN", "EM", "SUB", "ESC",
+    "FS", "GS", "RS", "US",
+];
 
 pub struct Codec {
     pub name: String,
-    codec: fn(src: &BytesMut),
+    codec: fn(src: &BytesMut, style: &Style) -> String,
+    timestamp: Timestamp,
+    style: Style,
+    triggers: Triggers,
+    frame: FrameOpt,
+    // Bytes of `src` already seen by decode, and when the last of them
+    // arrived.
+    pending: usize,
+    last_read: time::Instant,
+    sequence: Option<SequenceChecker>,
+    link: Option<Link>,
+    // Bytes the link layer wants written to the port.
+    outgoing: Vec<Bytes>,
+}
+
+// What the byte codecs need to know besides the bytes themselves.
+pub struct Style {
+    offset: usize,
+    color: bool,
+    highlights: Vec<Range<usize>>,
+}
+
+impl Style {
+    // Colors the text shown for the byte at `index` in the frame if it is
+    // part of a trigger match or a highlighted control byte. Without colors,
+    // trigger matches are put between brackets instead.
+    fn byte(&self, index: usize, byte: u8, text: String) -> String {
+        let offset = self.offset + index;
+        if !self.color {
+            let opened = self.highlights.iter().filter(|x| x.start == offset).count();
+            let closed = self
+                .highlights
+                .iter()
+                .filter(|x| x.end == offset + 1)
+                .count();
+            return "[".repeat(opened) + &text + &"]".repeat(closed);
+        }
+        if self.highlights.iter().any(|range| range.contains(&offset)) {
+            return color::paint(color::MATCH, &text);
+        }
+        match color::byte_color(byte) {
+            Some(byte_color) => color::paint(byte_color, &text),
+            None => text,
+        }
+    }
+}
+
+// Returns the symbolic name of an ASCII control character.
+pub fn control_name(byte: u8) -> Option<&'static str> {
+    match byte {
+        0x00..=0x1F => Some(CONTROL_NAMES[byte as usize]),
+        0x7F => Some("DEL"),
+        _ => None,
+    }
+}
+
+fn codec_hex(src: &BytesMut, style: &Style) -> String {
+    src.iter()
+        .enumerate()
+        .map(|(i, x)| style.byte(i, *x, format!("0x{:02X}", x)) + " ")
+        .collect()
 }
 
-fn codec_hex(src: &BytesMut) {
-    src.iter().for_each(|x| print!("0x{:02X} ", x));
+// Shows the fields of an Atellica message, or the hex bytes of anything
+// that doesn't decode as one. The fields don't map to bytes, so the bytes
+// of a message follow them to carry trigger matches and colors.
+fn codec_atellica(src: &BytesMut, style: &Style) -> String {
+    match atellica::message_factory(src) {
+        Some(message) => format!("{} | {}", message, codec_hex(src, style)),
+        None => codec_hex(src, style),
+    }
 }
 
-fn codec_dec(src: &BytesMut) {
-    src.iter().for_each(|x| print!("{} ", x));
+fn codec_dec(src: &BytesMut, style: &Style) -> String {
+    src.iter()
+        .enumerate()
+        .map(|(i, x)| style.byte(i, *x, x.to_string()) + " ")
+        .collect()
 }
 
-fn codec_char(src: &BytesMut) {
+fn codec_char(src: &BytesMut, style: &Style) -> String {
     src.iter()
-        .for_each(|x| print!("{}", ascii::escape_default(*x)));
+        .enumerate()
+        .map(|(i, x)| style.byte(i, *x, ascii::escape_default(*x).to_string()))
+        .collect()
+}
+
+// Formats up to 16 bytes, starting at `start` in the frame, like a
+// `hexdump -C` row, with control characters shown by name in the ASCII column.
+fn dump_row(start: usize, row: &[u8], style: &Style) -> String {
+    let mut hex = String::new();
+    for i in 0..DUMP_ROW_LEN {
+        if i == DUMP_ROW_LEN / 2 {
+            hex.push(' ');
+        }
+        match row.get(i) {
+            Some(x) => hex += &(style.byte(start + i, *x, format!("{:02x}", x)) + " "),
+            None => hex += "   ",
+        }
+    }
+
+    let text: String = row
+        .iter()
+        .enumerate()
+        .map(|(i, x)| match control_name(*x) {
+            Some(name) => style.byte(start + i, *x, format!("<{}>", name)),
+            None if x.is_ascii() => style.byte(start + i, *x, (*x as char).to_string()),
+            None => style.byte(start + i, *x, ".".to_string()),
+        })
+        .collect();
+
+    format!("{:08x}  {} |{}|", style.offset + start, hex, text)
+}
+
+fn codec_dump(src: &BytesMut, style: &Style) -> String {
+    src.chunks(DUMP_ROW_LEN)
+        .enumerate()
+        .map(|(i, row)| format!("\n{}", dump_row(i * DUMP_ROW_LEN, row, style)))
+        .collect()
 }
 
 impl Codec {
     pub fn new(name: String, opt: &Opt) -> Self {
         Self {
-            name,
+            name: opt.label.clone().unwrap_or(name),
             codec: match opt.codec {
                 CodecOpt::Hex => codec_hex,
                 CodecOpt::Dec => codec_dec,
                 CodecOpt::Char => codec_char,
+                CodecOpt::Dump => codec_dump,
+                CodecOpt::Atellica => codec_atellica,
             },
+            timestamp: Timestamp::new(opt),
+            style: Style {
+                offset: 0,
+                color: color::enabled(opt.color),
+                highlights: Vec::new(),
+            },
+            triggers: Triggers::new(opt),
+            // Sequence ids are only found at the start of whole messages.
+            frame: if opt.check_sequence {
+                FrameOpt::Atellica
+            } else {
+                opt.frame
+            },
+            pending: 0,
+            last_read: time::Instant::now(),
+            sequence: opt.check_sequence.then(SequenceChecker::new),
+            link: None,
+            outgoing: Vec::new(),
+        }
+    }
+
+    // Tells lines about data sent to the port from the received ones.
+    pub fn with_direction(mut self, direction: &str) -> Self {
+        self.name = format!("{} {}", self.name, direction);
+        self
+    }
+
+    // Runs the ACK/NAK link layer over the received frames, which are then
+    // split as Atellica messages. The caller writes what `take_outgoing`
+    // returns and calls `poll_link` at `link_deadline`.
+    pub fn with_link(mut self, settings: Option<LinkSettings>) -> Self {
+        if let Some(settings) = settings {
+            self.link = Some(Link::new(settings));
+            self.frame = FrameOpt::Atellica;
+        }
+        self
+    }
+
+    // Returns how long the port must stay quiet to end a frame, if frames
+    // are split that way.
+    pub fn idle(&self) -> Option<Duration> {
+        self.frame.idle()
+    }
+
+    // Returns the port name and timestamp that start every line.
+    fn prefix(&mut self) -> String {
+        let stamp = self.timestamp.stamp();
+        if self.style.color {
+            format!(
+                "{} | {} | ",
+                color::paint(color::port_color(&self.name), &self.name),
+                stamp
+            )
+        } else {
+            format!("{} | {} | ", self.name, stamp)
         }
     }
+
+    // Prints one frame with the port name and timestamp, then runs the
+    // actions of the triggers it matched, answers it on the link and reports
+    // Atellica sequence ids out of line.
+    pub fn display(&mut self, frame: &BytesMut) {
+        if self.exit_code().is_some() {
+            return;
+        }
+        let matches = self.triggers.feed(frame);
+        // Matches that began in an earlier frame or go on in the next one
+        // are highlighted on the bytes of this frame.
+        let end = self.style.offset + frame.len();
+        self.style.highlights = matches
+            .iter()
+            .map(|m| m.range.start.max(self.style.offset)..m.range.end.min(end))
+            .collect();
+
+        let line = self.prefix() + &(self.codec)(frame, &self.style);
+        self.style.offset += frame.len();
+
+        if matches.is_empty() {
+            self.triggers.show(line);
+        } else {
+            self.triggers.show_match(line);
+            for found in matches {
+                match self.triggers.fire(&self.name, &found) {
+                    Ok(description) => println!("{}{}", self.prefix(), description),
+                    Err(err) => self.error(&err),
+                }
+            }
+            self.triggers.stop();
+        }
+
+        if let Some(link) = &mut self.link {
+            let actions = link.receive(frame, Instant::now());
+            self.link_actions(actions);
+        }
+
+        if let Some(sequence) = &mut self.sequence {
+            let mut header = Header::default();
+            if header.decode(frame).is_ok() {
+                if let Some(issue) = sequence.check(&header) {
+                    self.event(&format!(
+                        "sequence from instrument {} {}",
+                        header.instrument_id(),
+                        issue
+                    ));
+                }
+            }
+        }
+    }
+
+    // Sends a frame through the link layer, or as it is without one.
+    pub fn send(&mut self, frame: Bytes) {
+        match &mut self.link {
+            Some(link) => {
+                let actions = link.send(frame, Instant::now());
+                self.link_actions(actions);
+            }
+            None => self.outgoing.push(frame),
+        }
+    }
+
+    // Returns when the frame waiting for its acknowledgement times out.
+    pub fn link_deadline(&self) -> Option<Instant> {
+        self.link.as_ref().and_then(Link::deadline)
+    }
+
+    // Retransmits the frame waiting for its acknowledgement if it timed out.
+    pub fn poll_link(&mut self) {
+        if let Some(link) = &mut self.link {
+            let actions = link.poll(Instant::now());
+            self.link_actions(actions);
+        }
+    }
+
+    // Returns true when no sent frame is waiting for its acknowledgement.
+    pub fn link_idle(&self) -> bool {
+        self.link.as_ref().is_none_or(Link::is_idle)
+    }
+
+    // Returns the bytes to write to the port, oldest first.
+    pub fn take_outgoing(&mut self) -> Vec<Bytes> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    // Queues what the link layer wants written and reports its events.
+    fn link_actions(&mut self, actions: Vec<LinkAction>) {
+        for action in actions {
+            match action {
+                LinkAction::Write(bytes) => self.outgoing.push(bytes),
+                LinkAction::Event(event) => self.event(&format!("link {}", event)),
+            }
+        }
+    }
+
+    // Prints something that happened on the port other than received bytes.
+    pub fn event(&mut self, text: &str) {
+        println!("{}{}", self.prefix(), text);
+    }
+
+    // Prints an error reported while reading from the port.
+    pub fn error(&mut self, err: &io::Error) {
+        let text = format!("error: {}", err);
+        if self.style.color {
+            println!("{}{}", self.prefix(), color::paint(color::RED, &text));
+        } else {
+            println!("{}{}", self.prefix(), text);
+        }
+    }
+
+    // When the last byte was received from the port.
+    pub fn last_read(&self) -> time::Instant {
+        self.last_read
+    }
+
+    // When the bytes waiting for the end of an idle frame make up a whole
+    // frame, unless more arrive before. None when there are none.
+    pub fn idle_deadline(&self) -> Option<time::Instant> {
+        let idle = self.frame.idle()?;
+        (self.pending > 0).then(|| self.last_read + idle)
+    }
+
+    // The code to exit with once a trigger match stopped the monitor. Frames
+    // are no longer displayed then.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.triggers.exit_code()
+    }
+
+    // Displays whatever is left in `src` as a frame of its own.
+    pub fn flush(&mut self, src: &mut BytesMut) {
+        if !src.is_empty() {
+            let frame = src.split();
+            self.display(&frame);
+        }
+        self.pending = 0;
+    }
 }
 
 impl Decoder for Codec {
-    type Item = String;
+    // Frames are displayed rather than returned; an item only tells the
+    // caller that the link layer has bytes to write.
+    type Item = ();
     type Error = io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let current_local: DateTime<Local> = Local::now();
-        print!("{} | {} | ", self.name, current_local.format("%X:%6f"));
-        (self.codec)(src);
-        println!();
-        src.clear();
+        if src.len() > self.pending {
+            // The bytes left over from the previous read ended a frame if
+            // the port stayed quiet for too long before this one.
+            if let Some(idle) = self.frame.idle() {
+                if self.pending > 0 && self.last_read.elapsed() > idle {
+                    let frame = src.split_to(self.pending);
+                    self.display(&frame);
+                }
+            }
+            self.last_read = time::Instant::now();
+        }
+
+        while let Some(frame) = self.frame.split(src) {
+            self.display(&frame);
+            if !self.outgoing.is_empty() {
+                self.pending = src.len();
+                return Ok(Some(()));
+            }
+        }
+        self.pending = src.len();
         Ok(None)
     }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(item) = self.decode(src)? {
+            return Ok(Some(item));
+        }
+        self.flush(src);
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    #[test]
+    fn dump_row_test() {
+        let style = Style {
+            offset: 0x10,
+            color: false,
+            highlights: Vec::new(),
+        };
+        assert_eq!(
+            dump_row(0, &[0x02, b'A', b'B', 0x03, 0x06, 0xFF], &style),
+            "00000010  02 41 42 03 06 ff                                 |<STX>AB<ETX><ACK>.|"
+        );
+    }
+
+    #[test]
+    fn match_brackets_test() {
+        let style = Style {
+            offset: 0x10,
+            color: false,
+            highlights: vec![0x11..0x13, 0x13..0x14],
+        };
+        assert_eq!(
+            codec_hex(&BytesMut::from(&[0x01, 0x02, 0x03, 0x04][..]), &style),
+            "0x01 [0x02 0x03] [0x04] "
+        );
+    }
+
+    #[test]
+    fn check_sequence_frame_test() {
+        let opt = Opt::from_iter(["serial-monitor", "--frame", "line", "--check-sequence"]);
+        assert_eq!(Codec::new("port".to_string(), &opt).frame, FrameOpt::Atellica);
+        let opt = Opt::from_iter(["serial-monitor", "--frame", "line"]);
+        assert_eq!(Codec::new("port".to_string(), &opt).frame, FrameOpt::Line);
+    }
+
+    #[test]
+    fn atellica_style_test() {
+        let style = Style {
+            offset: 0,
+            color: false,
+            highlights: vec![0x00..0x01, 0x16..0x18],
+        };
+        let frame = BytesMut::from(
+            &[
+                0x02, 0x00, 0x1e, 0x00, 0x01, 0x00, 0x00, 0x04, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x02, 0x53, 0x31, 0x01, 0x00, 0x00, 0x73,
+                0x18, 0x03,
+            ][..],
+        );
+        let line = codec_atellica(&frame, &style);
+        assert!(line.starts_with("AddQueueRequest seq 0x0001"));
+        assert!(line.contains(" | [0x02] 0x00 0x1E "));
+        assert!(line.ends_with("0x02 [0x53 0x31] 0x01 0x00 0x00 0x73 0x18 0x03 "));
+    }
 }
@@ -0,0 +1,48 @@
+use std::io::{self, IsTerminal};
+
+use crate::opt::ColorOpt;
+
+pub const RESET: &str = "\x1b[0m";
+pub const RED: &str = "\x1b[31m";
+pub const MATCH: &str = "\x1b[7m";
+
+const FRAMING: &str = "\x1b[1;33m";
+const ACK: &str = "\x1b[1;32m";
+const NAK: &str = "\x1b[1;31m";
+
+const PORT_COLORS: [&str; 6] = [
+    "\x1b[36m", "\x1b[35m", "\x1b[34m", "\x1b[32m", "\x1b[33m", "\x1b[96m",
+];
+
+// Returns true if the output should contain ANSI color codes.
+pub fn enabled(opt: ColorOpt) -> bool {
+    match opt {
+        ColorOpt::Always => true,
+        ColorOpt::Never => false,
+        ColorOpt::Auto => io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+    }
+}
+
+// Picks a color for a port name. The same name always gets the same color,
+// so a port keeps its color from one run to the next.
+pub fn port_color(name: &str) -> &'static str {
+    // FNV-1a, which unlike the std hasher is stable across releases.
+    let hash = name.bytes().fold(0x811c9dc5u32, |hash, x| {
+        (hash ^ x as u32).wrapping_mul(0x01000193)
+    });
+    PORT_COLORS[hash as usize % PORT_COLORS.len()]
+}
+
+// Returns the color used to highlight a protocol control byte.
+pub fn byte_color(byte: u8) -> Option<&'static str> {
+    match byte {
+        0x02 | 0x03 => Some(FRAMING),
+        0x06 => Some(ACK),
+        0x15 => Some(NAK),
+        _ => None,
+    }
+}
+
+pub fn paint(color: &str, text: &str) -> String {
+    format!("{}{}{}", color, text, RESET)
+}
@@ -0,0 +1,91 @@
+use serial_monitor::atellica::enums::{InterfacePositionIndex, SamplePriority};
+use serial_monitor::atellica::{BuildError, Message, SampleIdPolicy, SequenceAllocator};
+use std::str::FromStr;
+
+use crate::opt::MessageArgs;
+
+const EXPECTED: &str = "add-queue:SAMPLE_ID";
+
+/// An Atellica message named on the command line
+#[derive(Clone, Debug, PartialEq)]
+pub enum MessageOpt {
+    AddQueue(String),
+}
+
+impl FromStr for MessageOpt {
+    type Err = String;
+
+    // Parses "add-queue:SAMPLE_ID".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("add-queue", sample_id)) => Ok(MessageOpt::AddQueue(sample_id.to_string())),
+            _ => Err(format!("invalid message '{}' (expected {})", s, EXPECTED)),
+        }
+    }
+}
+
+pub fn parse_ip(s: &str) -> Result<InterfacePositionIndex, String> {
+    s.parse::<u8>()
+        .ok()
+        .and_then(|x| InterfacePositionIndex::try_from(x).ok())
+        .ok_or_else(|| format!("invalid interface position '{}' (expected 0 or 1)", s))
+}
+
+impl MessageOpt {
+    // Builds the message with the header and tube settings of `args`. The
+    // sequence id is left to the caller.
+    pub fn build(&self, args: &MessageArgs) -> Result<Message, BuildError> {
+        let policy = if args.truncate_sample_ids {
+            SampleIdPolicy::Truncate
+        } else {
+            SampleIdPolicy::Reject
+        };
+        let priority = if args.stat {
+            SamplePriority::STAT
+        } else {
+            SamplePriority::Routine
+        };
+        let builder = Message::builder()
+            .instrument(args.instrument)
+            .sample_id_policy(policy);
+        match self {
+            MessageOpt::AddQueue(sample_id) => builder
+                .add_queue_request()
+                .ip(args.ip)
+                .tube(|t| t.sample_id(sample_id).priority(priority))
+                .build(),
+        }
+    }
+}
+
+// Builds the messages named on the command line, numbering them from the
+// first sequence id of their instrument.
+pub fn build_messages(args: &MessageArgs) -> Result<Vec<Message>, BuildError> {
+    let mut sequence = SequenceAllocator::new();
+    args.messages
+        .iter()
+        .map(|message| {
+            let mut message = message.build(args)?;
+            sequence.assign(&mut message);
+            Ok(message)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str_test() {
+        assert!("keep-alive".parse::<MessageOpt>().is_err());
+        assert_eq!(
+            "add-queue:S-1".parse(),
+            Ok(MessageOpt::AddQueue("S-1".to_string()))
+        );
+        assert!("add-queue".parse::<MessageOpt>().is_err());
+        assert!("status:1".parse::<MessageOpt>().is_err());
+        assert_eq!(parse_ip("1"), Ok(InterfacePositionIndex::IP1));
+        assert!(parse_ip("2").is_err());
+    }
+}
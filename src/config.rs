@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, fs};
+use structopt::clap::ArgMatches;
+use toml::{Table, Value};
+
+use crate::error::ProgramError;
+use crate::opt::Opt;
+use crate::port_settings::PortSettings;
+
+const FILE_NAME: &str = "config.toml";
+const PROJECT_FILE_NAME: &str = "serial_monitor.toml";
+
+// Port settings saved under a name. Every key is the long name of a command
+// line option and its value is parsed like the option, except for "ports",
+// a list of tables of --port-settings keys.
+type Profile = Table;
+
+// Returns the per-user configuration file, e.g. ~/.config/serial_monitor/config.toml
+fn user_config() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    dir.map(|dir| dir.join("serial_monitor").join(FILE_NAME))
+}
+
+// Reads the profiles of a file, the tables under [profiles].
+fn read_config(path: &Path) -> Result<HashMap<String, Profile>, ProgramError> {
+    let error = |e: String| ProgramError::ConfigError(format!("{}: {}", path.display(), e));
+    let text = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    let mut file: Table = text
+        .parse()
+        .map_err(|e: toml::de::Error| error(e.to_string()))?;
+    let profiles = match file.remove("profiles") {
+        None => Table::new(),
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => return Err(error("profiles is not a table".to_string())),
+    };
+    if let Some(key) = file.keys().next() {
+        return Err(error(format!("unknown key '{}'", key)));
+    }
+    profiles
+        .into_iter()
+        .map(|(name, profile)| match profile {
+            Value::Table(profile) => Ok((name, profile)),
+            _ => Err(error(format!("profile '{}' is not a table", name))),
+        })
+        .collect()
+}
+
+// Loads the named profile. The per-user file is read first, then the file
+// of the current project, then the file given with --config; a profile in
+// a later file replaces one with the same name in an earlier file.
+fn find_profile(name: &str, config: Option<&Path>) -> Result<Profile, ProgramError> {
+    let mut paths: Vec<PathBuf> = user_config()
+        .into_iter()
+        .chain([PathBuf::from(PROJECT_FILE_NAME)])
+        .filter(|path| path.is_file())
+        .collect();
+    paths.extend(config.map(Path::to_path_buf));
+
+    let mut profiles = HashMap::new();
+    for path in paths {
+        profiles.extend(read_config(&path)?);
+    }
+    profiles
+        .remove(name)
+        .ok_or_else(|| ProgramError::ConfigError(format!("profile '{}' not found", name)))
+}
+
+// Returns a value as it would be written on the command line.
+fn text(value: Value) -> String {
+    match value {
+        Value::String(value) => value,
+        value => value.to_string(),
+    }
+}
+
+fn parse_value<T>(name: &str, value: String) -> Result<T, ProgramError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| ProgramError::ConfigError(format!("{} '{}': {}", name, value, e)))
+}
+
+// Removes an option from the profile and parses it.
+fn take<T>(profile: &mut Profile, name: &str) -> Result<Option<T>, ProgramError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    profile
+        .remove(name)
+        .map(|value| parse_value(name, text(value)))
+        .transpose()
+}
+
+// Removes an option given as a list from the profile and parses its items.
+fn take_list<T>(profile: &mut Profile, name: &str) -> Result<Option<Vec<T>>, ProgramError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match profile.remove(name) {
+        None => Ok(None),
+        Some(Value::Array(values)) => values
+            .into_iter()
+            .map(|value| parse_value(name, text(value)))
+            .collect::<Result<_, _>>()
+            .map(Some),
+        Some(value) => Err(ProgramError::ConfigError(format!(
+            "{} '{}': expected a list",
+            name, value
+        ))),
+    }
+}
+
+// Removes the per-port settings from the profile.
+fn take_ports(profile: &mut Profile) -> Result<Option<Vec<PortSettings>>, ProgramError> {
+    let error = |e: String| ProgramError::ConfigError(format!("ports: {}", e));
+    let ports = match profile.remove("ports") {
+        None => return Ok(None),
+        Some(Value::Array(ports)) => ports,
+        Some(value) => return Err(error(format!("expected a list of tables, not {}", value))),
+    };
+    ports
+        .into_iter()
+        .map(|port| {
+            let Value::Table(port) = port else {
+                return Err(error(format!("expected a table, not {}", port)));
+            };
+            let values: Vec<(String, String)> = port
+                .into_iter()
+                .map(|(key, value)| (key, text(value)))
+                .collect();
+            PortSettings::from_pairs(values.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+                .map_err(error)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+// Uses the profile value for an option that was not given on the command
+// line. Arguments are named after their long option, in kebab case, and so
+// are the keys of a profile.
+macro_rules! merge {
+    ($opt:ident, $matches:ident, $profile:ident, $field:ident) => {
+        merge!($opt, $matches, $profile, $field, |value| value)
+    };
+    ($opt:ident, $matches:ident, $profile:ident, $field:ident, $wrap:expr) => {
+        let name = stringify!($field).replace('_', "-");
+        if let Some(value) = take(&mut $profile, &name)? {
+            if $matches.occurrences_of(&name) == 0 {
+                $opt.$field = $wrap(value);
+            }
+        }
+    };
+}
+
+// Fills in the options that were not given on the command line from the
+// profile selected with --profile.
+pub fn apply_profile(opt: &mut Opt, matches: &ArgMatches) -> Result<(), ProgramError> {
+    let name = match &opt.profile {
+        Some(name) => name.clone(),
+        None => return Ok(()),
+    };
+    let mut p = find_profile(&name, opt.config.as_deref())?;
+
+    merge!(opt, matches, p, port, Some);
+    merge!(opt, matches, p, baud);
+    merge!(opt, matches, p, vid, Some);
+    merge!(opt, matches, p, pid, Some);
+    merge!(opt, matches, p, manufacturer, Some);
+    merge!(opt, matches, p, serial, Some);
+    merge!(opt, matches, p, product, Some);
+    merge!(opt, matches, p, index, Some);
+    merge!(opt, matches, p, parity);
+    merge!(opt, matches, p, stopbits);
+    merge!(opt, matches, p, flow);
+    merge!(opt, matches, p, databits);
+    merge!(opt, matches, p, auto_baud, Some);
+    merge!(opt, matches, p, auto_baud_ms);
+    merge!(opt, matches, p, auto_baud_combos);
+    merge!(opt, matches, p, codec);
+    merge!(opt, matches, p, frame);
+    merge!(opt, matches, p, check_sequence);
+    merge!(opt, matches, p, link);
+    merge!(opt, matches, p, link_timeout_ms);
+    merge!(opt, matches, p, link_retries);
+    merge!(opt, matches, p, link_check_crc);
+    if let Some(trigger) = take_list(&mut p, "trigger")? {
+        if matches.occurrences_of("trigger") == 0 {
+            opt.trigger = trigger;
+        }
+    }
+    merge!(opt, matches, p, trigger_context, Some);
+    merge!(opt, matches, p, trigger_exec, Some);
+    merge!(opt, matches, p, trigger_exit, Some);
+    merge!(opt, matches, p, modem_lines);
+    merge!(opt, matches, p, modem_poll_ms);
+    merge!(opt, matches, p, dtr, Some);
+    merge!(opt, matches, p, rts, Some);
+    merge!(opt, matches, p, break_ms, Some);
+    merge!(opt, matches, p, color);
+    merge!(opt, matches, p, timestamp);
+    merge!(opt, matches, p, timestamp_format, Some);
+    merge!(opt, matches, p, label, Some);
+    if let Some(port_settings) = take_ports(&mut p)? {
+        if matches.occurrences_of("port-settings") == 0 {
+            opt.port_settings = port_settings;
+        }
+    }
+
+    if let Some(key) = p.keys().next() {
+        return Err(ProgramError::ConfigError(format!(
+            "profile '{}': unknown option '{}'",
+            name, key
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::{CodecOpt, ParityOpt};
+    use structopt::StructOpt;
+
+    #[test]
+    fn apply_profile_test() {
+        let path = env::temp_dir().join("serial_monitor_apply_profile_test.toml");
+        fs::write(
+            &path,
+            r#"
+            [profiles.atellica-ip1]
+            vid = "0403"
+            baud = 115200
+            parity = "even"
+            codec = "char"
+            modem-poll-ms = 500
+            timestamp-format = "%H:%M"
+
+            [[profiles.atellica-ip1.ports]]
+            serial = "A1B2"
+            baud = 19200
+            label = "analyzer"
+            "#,
+        )
+        .unwrap();
+
+        let args = [
+            "serial-monitor",
+            "--profile",
+            "atellica-ip1",
+            "--config",
+            path.to_str().unwrap(),
+            "--codec",
+            "dump",
+            "--modem-poll-ms",
+            "50",
+            "--port-settings",
+            "index=1,baud=9600",
+        ];
+        let matches = Opt::clap().get_matches_from(args);
+        let mut opt = Opt::from_clap(&matches);
+        let result = apply_profile(&mut opt, &matches);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        assert_eq!(opt.vid.as_deref(), Some("0403"));
+        assert_eq!(opt.baud, 115200);
+        assert_eq!(opt.parity, ParityOpt::Even);
+        assert_eq!(opt.codec, CodecOpt::Dump);
+        assert_eq!(opt.modem_poll_ms, 50);
+        assert_eq!(opt.timestamp_format.as_deref(), Some("%H:%M"));
+        assert_eq!(
+            opt.port_settings,
+            vec!["index=1,baud=9600".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn unknown_option_test() {
+        let path = env::temp_dir().join("serial_monitor_unknown_option_test.toml");
+        fs::write(&path, "[profiles.lab]\nbaud = 9600\nspeed = 9600\n").unwrap();
+
+        let args = [
+            "serial-monitor",
+            "--profile",
+            "lab",
+            "--config",
+            path.to_str().unwrap(),
+        ];
+        let matches = Opt::clap().get_matches_from(args);
+        let mut opt = Opt::from_clap(&matches);
+        let result = apply_profile(&mut opt, &matches);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "configuration: profile 'lab': unknown option 'speed'"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use tokio::io::{self, AsyncReadExt};
+use tokio::sync::broadcast;
+
+const HELP: &str = "keys: d (toggle DTR), r (toggle RTS), b (send BREAK)";
+
+// Changes to the control lines requested while monitoring.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Control {
+    ToggleDtr,
+    ToggleRts,
+    Break,
+}
+
+impl Control {
+    // Returns the control bound to a key, if any.
+    pub fn from_key(key: u8) -> Option<Self> {
+        match key.to_ascii_lowercase() {
+            b'd' => Some(Control::ToggleDtr),
+            b'r' => Some(Control::ToggleRts),
+            b'b' => Some(Control::Break),
+            _ => None,
+        }
+    }
+}
+
+// Puts the terminal back the way it was when dropped.
+pub struct Terminal;
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        terminal::restore();
+    }
+}
+
+// Reads single key presses from stdin and sends the controls they stand for
+// to every port. When stdin is a terminal, keys are read as soon as they are
+// pressed, without Enter and without echo.
+pub fn spawn_keys(sender: broadcast::Sender<Control>) -> Terminal {
+    eprintln!("{}", HELP);
+    if terminal::read_keys() {
+        tokio::task::spawn(async {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                terminal::restore();
+                std::process::exit(130);
+            }
+        });
+    }
+    tokio::task::spawn(async move {
+        let mut stdin = io::stdin();
+        let mut keys = [0; 64];
+        while let Ok(n @ 1..) = stdin.read(&mut keys).await {
+            for key in &keys[..n] {
+                match Control::from_key(*key) {
+                    Some(control) => {
+                        // Nobody listening just means every port was closed.
+                        let _ = sender.send(control);
+                    }
+                    None if key.is_ascii_whitespace() => {}
+                    None => eprintln!("{}", HELP),
+                }
+            }
+        }
+    });
+    Terminal
+}
+
+// Puts the terminal back the way it was, if keys were read from it.
+pub fn restore_terminal() {
+    terminal::restore();
+}
+
+#[cfg(unix)]
+mod terminal {
+    use nix::sys::termios::{self, LocalFlags, SetArg, Termios};
+    use std::io::{self, IsTerminal};
+    use std::sync::Mutex;
+
+    // Settings of the terminal before keys were read from it.
+    static SAVED: Mutex<Option<Termios>> = Mutex::new(None);
+
+    // Turns off line editing and echo, so that each key is read as it is
+    // pressed. Ctrl-C still interrupts. Returns true if the terminal was
+    // changed.
+    pub fn read_keys() -> bool {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return false;
+        }
+        let Ok(saved) = termios::tcgetattr(&stdin) else {
+            return false;
+        };
+        let mut keys = saved.clone();
+        keys.local_flags.remove(LocalFlags::ICANON | LocalFlags::ECHO);
+        if termios::tcsetattr(&stdin, SetArg::TCSANOW, &keys).is_err() {
+            return false;
+        }
+        *SAVED.lock().unwrap() = Some(saved);
+        true
+    }
+
+    pub fn restore() {
+        if let Some(saved) = SAVED.lock().unwrap().take() {
+            let _ = termios::tcsetattr(io::stdin(), SetArg::TCSANOW, &saved);
+        }
+    }
+}
+
+// Other consoles hand over what was typed once Enter is pressed.
+#[cfg(not(unix))]
+mod terminal {
+    pub fn read_keys() -> bool {
+        false
+    }
+
+    pub fn restore() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_key_test() {
+        assert_eq!(Control::from_key(b'd'), Some(Control::ToggleDtr));
+        assert_eq!(Control::from_key(b'R'), Some(Control::ToggleRts));
+        assert_eq!(Control::from_key(b'b'), Some(Control::Break));
+        assert_eq!(Control::from_key(b'x'), None);
+    }
+}
@@ -0,0 +1,204 @@
+use bytes::BytesMut;
+use serial_monitor::atellica::footer::{self, FOOTER_LEN};
+use serial_monitor::atellica::header::{HEADER_LEN, STX};
+use serial_monitor::atellica::link::{ACK, NAK};
+use serial_monitor::atellica::{self, DecodeError, FrameError};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::color;
+use crate::error::ProgramError;
+use crate::framing::FrameOpt;
+use crate::opt::{DecodeOpt, Opt};
+use crate::trigger::parse_hex;
+
+// Returns the bytes of a hex dump: bare hex ("02 00 15", "020015"), with 0x
+// prefixes and commas, or lines printed by the monitor with the hex codec
+// ("port | 12:00:00.000 | 0x02 0x00 ..."). Monitor lines that don't hold
+// bytes, like events and errors, are skipped.
+pub fn parse_dump(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let (hex, from_monitor) = match line.rsplit_once(" | ") {
+            Some((_, hex)) => (hex, true),
+            None => (line, false),
+        };
+        if hex.trim().is_empty() {
+            continue;
+        }
+        match parse_hex(hex) {
+            Ok(line_bytes) => bytes.extend(line_bytes),
+            Err(_) if from_monitor => {}
+            Err(err) => return Err(format!("line {}: {}", number + 1, err)),
+        }
+    }
+    Ok(bytes)
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Describes one frame split from the dump, and lists what is wrong with it.
+fn describe(frame: &BytesMut, rest: &BytesMut, check_crc: bool) -> (String, Vec<FrameError>) {
+    match &frame[..] {
+        [ACK] => ("ACK".to_string(), Vec::new()),
+        [NAK] => ("NAK".to_string(), Vec::new()),
+        // The splitter skips an STX whose length can't be right, the length
+        // is in the bytes that follow it.
+        [STX] => {
+            let length = u16::from_be_bytes([rest[0], rest[1]]);
+            let error = FrameError {
+                offset: 1,
+                error: DecodeError::InvalidField,
+            };
+            let text = format!(
+                "STX with length {}, shorter than a header and footer ({} bytes)",
+                length,
+                HEADER_LEN + FOOTER_LEN
+            );
+            (text, vec![error])
+        }
+        [STX, ..] => {
+            let crc = footer::check_crc(frame)
+                .err()
+                .filter(|error| check_crc || !matches!(error, DecodeError::BadCrc { .. }))
+                .map(|error| FrameError {
+                    offset: frame.len() - FOOTER_LEN,
+                    error,
+                });
+            match atellica::decode_frame(frame) {
+                Ok(message) => (message.to_string(), crc.into_iter().collect()),
+                Err(err) => (hex(frame), [Some(err), crc].into_iter().flatten().collect()),
+            }
+        }
+        bytes => (format!("{} (outside of a frame)", hex(bytes)), Vec::new()),
+    }
+}
+
+// Splits the bytes into Atellica frames and describes them, a line per
+// frame preceded by its offset in the dump, followed by a line for each
+// error at the offset of the byte at fault. Bad CRCs are only errors with
+// `check_crc`.
+pub fn report(bytes: &[u8], check_crc: bool, color: bool) -> Vec<String> {
+    let mut src = BytesMut::from(bytes);
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while !src.is_empty() {
+        let (frame, (text, errors)) = match FrameOpt::Atellica.split(&mut src) {
+            Some(frame) => {
+                let description = describe(&frame, &src, check_crc);
+                (frame, description)
+            }
+            // Only the start of a frame is left.
+            None => {
+                let frame = src.split();
+                let error = FrameError {
+                    offset: frame.len(),
+                    error: DecodeError::TooShort,
+                };
+                let text = format!("{} (cut off)", hex(&frame));
+                (frame, (text, vec![error]))
+            }
+        };
+        lines.push(format!("{:08x}  {}", offset, text));
+        for error in errors {
+            let text = format!("error: {}", error);
+            let text = if color {
+                color::paint(color::RED, &text)
+            } else {
+                text
+            };
+            lines.push(format!("{:08x}  {}", offset + error.offset, text));
+        }
+        offset += frame.len();
+    }
+    lines
+}
+
+// Decodes the Atellica frames of a hex dump or capture file, or of stdin.
+pub fn run(opt: &Opt, decode: &DecodeOpt) -> Result<(), ProgramError> {
+    let mut input = Vec::new();
+    match decode.file.as_deref() {
+        Some(path) if path != Path::new("-") => {
+            input = fs::read(path).map_err(ProgramError::IoError)?;
+        }
+        _ => {
+            io::stdin()
+                .read_to_end(&mut input)
+                .map_err(ProgramError::IoError)?;
+        }
+    }
+
+    let bytes = if decode.raw {
+        input
+    } else {
+        parse_dump(&String::from_utf8_lossy(&input)).map_err(ProgramError::InvalidHexDump)?
+    };
+    for line in report(&bytes, decode.check_crc, color::enabled(opt.color)) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_monitor::atellica::enums::{AddQueueCommandStatusValues, InterfacePositionIndex};
+    use serial_monitor::atellica::{AddQueueResponse, Info, Message, Payload};
+
+    #[test]
+    fn parse_dump_test() {
+        let dump = "ttyUSB0 | 12:00:00.000 | 0x02 0x00 0x15 \n\
+                    ttyUSB0 | 12:00:00.001 | link ACK for 0x0001\n\
+                    \n\
+                    06 15\n\
+                    0x03,0x04";
+        assert_eq!(
+            parse_dump(dump),
+            Ok(vec![0x02, 0x00, 0x15, 0x06, 0x15, 0x03, 0x04])
+        );
+        assert_eq!(
+            parse_dump("02 00\nnot hex"),
+            Err("line 2: invalid hex bytes 'not hex'".to_string())
+        );
+    }
+
+    #[test]
+    fn report_test() {
+        let response = AddQueueResponse::new(
+            InterfacePositionIndex::IP0,
+            "S-1".to_string(),
+            AddQueueCommandStatusValues::Ok,
+        );
+        let mut message = Message::new(0, 10, Payload::AddQueueResponse(response));
+        message.set_sequence_id(1);
+        let frame = message.encode().unwrap();
+        let mut bad_crc = frame.clone();
+        bad_crc[frame.len() - 2] ^= 0xFF;
+
+        let mut bytes = vec![ACK, 0x01];
+        bytes.extend_from_slice(&frame);
+        bytes.extend_from_slice(&bad_crc);
+        bytes.extend_from_slice(&frame[..5]);
+
+        let lines = report(&bytes, true, false);
+        assert_eq!(lines[0], "00000000  ACK");
+        assert_eq!(lines[1], "00000001  01 (outside of a frame)");
+        assert_eq!(lines[2], format!("00000002  {}", message));
+        assert_eq!(lines[3], format!("0000001d  {}", message));
+        assert!(lines[4].starts_with("00000035  error: bad CRC"));
+        assert_eq!(lines[5], "00000038  02 00 1b 00 01 (cut off)");
+        assert_eq!(lines[6], "0000003d  error: frame is too short at offset 5");
+        assert_eq!(lines.len(), 7);
+
+        let lines = report(&bytes, false, false);
+        assert_eq!(lines[4], "00000038  02 00 1b 00 01 (cut off)");
+        assert_eq!(lines.len(), 6);
+    }
+}
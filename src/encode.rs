@@ -0,0 +1,138 @@
+use serial_monitor::atellica::{BuildError, Info, Message};
+use std::path::Path;
+
+use crate::compose;
+use crate::decode::hex;
+use crate::error::ProgramError;
+use crate::opt::{EncodeFormatOpt, EncodeOpt};
+
+// Bytes per line of a C array.
+const C_ROW_LEN: usize = 12;
+
+/// Messages read from a file, as [[message]] tables in TOML
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MessageFile {
+    message: Vec<Message>,
+}
+
+// Reads the messages of a TOML file, or of a JSON one when its name ends
+// in .json. Their sequence ids are kept as written.
+#[cfg(feature = "serde")]
+fn read_messages(path: &Path) -> Result<Vec<Message>, ProgramError> {
+    let error =
+        |err: String| ProgramError::InvalidMessageFile(format!("{}: {}", path.display(), err));
+    let text = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    let file: MessageFile = if path.extension().is_some_and(|x| x == "json") {
+        serde_json::from_str(&text).map_err(|e| error(e.to_string()))?
+    } else {
+        toml::from_str(&text).map_err(|e| error(e.to_string()))?
+    };
+    Ok(file.message)
+}
+
+#[cfg(not(feature = "serde"))]
+fn read_messages(path: &Path) -> Result<Vec<Message>, ProgramError> {
+    Err(ProgramError::InvalidMessageFile(format!(
+        "{}: built without the serde feature",
+        path.display()
+    )))
+}
+
+// Formats the index'th frame, as bytes for the hex and dec formats, or as
+// a C array behind a comment describing the message.
+fn format_frame(frame: &[u8], format: EncodeFormatOpt, index: usize, message: &Message) -> String {
+    match format {
+        EncodeFormatOpt::Hex => hex(frame),
+        EncodeFormatOpt::Dec => frame
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        EncodeFormatOpt::C => {
+            let rows: String = frame
+                .chunks(C_ROW_LEN)
+                .map(|row| {
+                    let row: Vec<_> = row.iter().map(|x| format!("0x{:02x},", x)).collect();
+                    format!("    {}\n", row.join(" "))
+                })
+                .collect();
+            // A sample id holding "*/" would end the comment early.
+            let description = message.to_string().replace("*/", "* /");
+            format!(
+                "/* {} */\nconst uint8_t frame_{}[{}] = {{\n{}}};",
+                description.trim_end(),
+                index,
+                frame.len(),
+                rows
+            )
+        }
+    }
+}
+
+// Prints the frames of the messages from --file, then of those given with
+// --message, one per line, with their length and CRC filled in.
+pub fn run(encode: &EncodeOpt) -> Result<(), ProgramError> {
+    let mut messages = match &encode.file {
+        Some(path) => read_messages(path)?,
+        None => Vec::new(),
+    };
+    messages
+        .extend(compose::build_messages(&encode.message).map_err(ProgramError::InvalidMessage)?);
+
+    for (index, mut message) in messages.into_iter().enumerate() {
+        let frame = message
+            .encode()
+            .ok_or(ProgramError::InvalidMessage(BuildError::Encode))?;
+        println!(
+            "{}",
+            format_frame(&frame, encode.format, index + 1, &message)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_frame_test() {
+        let frame = [
+            0x02, 0x00, 0x15, 0x00, 0x01, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let message = Message::default();
+        assert_eq!(
+            format_frame(&frame[..3], EncodeFormatOpt::Dec, 1, &message),
+            "2 0 21"
+        );
+        assert_eq!(
+            format_frame(&frame, EncodeFormatOpt::C, 2, &message),
+            "/* NoMessageType seq 0x0000 ret 0x0000 instrument 0 length 0 no message */\n\
+             const uint8_t frame_2[13] = {\n    \
+             0x02, 0x00, 0x15, 0x00, 0x01, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00,\n    \
+             0x00,\n\
+             };"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn message_file_test() {
+        use serial_monitor::atellica::{message_factory, Payload};
+
+        let file: MessageFile = toml::from_str(
+            r#"
+            [[message]]
+            header = { sequence_id = 5, return_sequence_id = 0, instrument_id = 1 }
+            payload.AddQueueResponse = { interface_position_index = "IP1", sample_id = "S-42", command_status = "Ok" }
+            "#,
+        )
+        .unwrap();
+        let mut message = file.message.into_iter().next().unwrap();
+        let decoded = message_factory(&message.encode().unwrap()).unwrap();
+        assert_eq!(decoded.header().sequence_id, 5);
+        assert!(matches!(decoded.payload(), Payload::AddQueueResponse(_)));
+    }
+}
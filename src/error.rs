@@ -1,7 +1,35 @@
+use serial_monitor::atellica::BuildError;
+use std::fmt;
+
 #[derive(Debug)]
 pub enum ProgramError {
     NoPortFound,
     UnableToOpen(String, tokio_serial::Error),
     IoError(std::io::Error),
     SerialPortError(tokio_serial::Error),
+    InvalidTimestampFormat(String),
+    ConfigError(String),
+    InvalidMessage(BuildError),
+    InvalidHexDump(String),
+    InvalidMessageFile(String),
 }
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgramError::NoPortFound => write!(f, "no port found"),
+            ProgramError::UnableToOpen(path, err) => write!(f, "unable to open {}: {}", path, err),
+            ProgramError::IoError(err) => write!(f, "{}", err),
+            ProgramError::SerialPortError(err) => write!(f, "{}", err),
+            ProgramError::InvalidTimestampFormat(format) => {
+                write!(f, "invalid timestamp format '{}'", format)
+            }
+            ProgramError::ConfigError(err) => write!(f, "configuration: {}", err),
+            ProgramError::InvalidMessage(err) => write!(f, "invalid message: {}", err),
+            ProgramError::InvalidHexDump(err) => write!(f, "invalid hex dump: {}", err),
+            ProgramError::InvalidMessageFile(err) => write!(f, "invalid message file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
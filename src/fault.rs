@@ -0,0 +1,199 @@
+use bytes::BytesMut;
+use serial_monitor::atellica::footer::{self, Footer};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Drop,
+    Corrupt,
+    FlipCrc,
+    Delay(u64),
+    Duplicate,
+}
+
+/// A fault injected into a share of the forwarded frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fault {
+    kind: Kind,
+    probability: f64,
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    // Parses "drop:0.1", "corrupt:0.1", "flip-crc:0.1", "delay:500:0.1" or
+    // "duplicate:0.1", the last number being the share of frames affected.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, probability) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected fault:probability, found '{}'", s))?;
+        let probability = match probability.parse() {
+            Ok(x) if (0.0..=1.0).contains(&x) => x,
+            _ => return Err(format!("invalid probability '{}'", probability)),
+        };
+        let kind = match kind.split_once(':') {
+            Some(("delay", ms)) => {
+                Kind::Delay(ms.parse().map_err(|_| format!("invalid delay '{}'", ms))?)
+            }
+            None if kind == "drop" => Kind::Drop,
+            None if kind == "corrupt" => Kind::Corrupt,
+            None if kind == "flip-crc" => Kind::FlipCrc,
+            None if kind == "duplicate" => Kind::Duplicate,
+            _ => {
+                return Err(format!(
+                    "invalid fault '{}' (expected drop, corrupt, flip-crc, delay:ms or duplicate)",
+                    kind
+                ))
+            }
+        };
+        Ok(Self { kind, probability })
+    }
+}
+
+// xorshift64*, good enough to pick frames and repeatable from its seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Returns a number in [0, 1).
+    fn fraction(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// Returns a seed for runs that did not ask for one.
+pub fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or(1)
+}
+
+// What happens to a frame once the faults are applied.
+#[derive(Debug, Default, PartialEq)]
+pub struct Outcome {
+    // Number of times the frame is sent, 0 when it is dropped.
+    pub copies: usize,
+    pub delay: Duration,
+    // Describes each fault applied, for the log.
+    pub faults: Vec<String>,
+}
+
+// Applies the faults to the frames going one way.
+pub struct Injector {
+    faults: Vec<Fault>,
+    rng: Rng,
+}
+
+impl Injector {
+    pub fn new(faults: &[Fault], seed: u64) -> Self {
+        Self {
+            faults: faults.to_vec(),
+            // xorshift never leaves 0.
+            rng: Rng(seed.max(1)),
+        }
+    }
+
+    // Decides what happens to `frame`, which may be changed in place.
+    pub fn apply(&mut self, frame: &mut BytesMut) -> Outcome {
+        let mut outcome = Outcome {
+            copies: 1,
+            ..Default::default()
+        };
+        for fault in &self.faults {
+            if self.rng.fraction() >= fault.probability {
+                continue;
+            }
+            match fault.kind {
+                Kind::Drop => {
+                    outcome.copies = 0;
+                    outcome.faults.push("dropped".to_string());
+                    break;
+                }
+                Kind::Corrupt if !frame.is_empty() => {
+                    let index = self.rng.below(frame.len());
+                    // Any non-zero mask changes the byte.
+                    let mask = self.rng.below(255) as u8 + 1;
+                    frame[index] ^= mask;
+                    outcome
+                        .faults
+                        .push(format!("corrupted byte {} with mask 0x{:02x}", index, mask));
+                }
+                Kind::Corrupt => {}
+                Kind::FlipCrc => {
+                    let mut footer = Footer::default();
+                    if footer.decode(frame).is_ok() {
+                        footer.flip_crc();
+                        let start = frame.len() - footer::FOOTER_LEN;
+                        frame[start..].copy_from_slice(&footer.encode().unwrap());
+                        outcome.faults.push("flipped CRC".to_string());
+                    }
+                }
+                Kind::Delay(ms) => {
+                    outcome.delay += Duration::from_millis(ms);
+                    outcome.faults.push(format!("delayed {} ms", ms));
+                }
+                Kind::Duplicate => {
+                    outcome.copies += 1;
+                    outcome.faults.push("duplicated".to_string());
+                }
+            }
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str_test() {
+        assert_eq!(
+            "delay:500:0.25".parse(),
+            Ok(Fault {
+                kind: Kind::Delay(500),
+                probability: 0.25
+            })
+        );
+        assert_eq!(
+            "flip-crc:1".parse(),
+            Ok(Fault {
+                kind: Kind::FlipCrc,
+                probability: 1.0
+            })
+        );
+        assert!("drop".parse::<Fault>().is_err());
+        assert!("drop:2".parse::<Fault>().is_err());
+        assert!("lose:0.5".parse::<Fault>().is_err());
+    }
+
+    #[test]
+    fn apply_test() {
+        let faults: Vec<Fault> = ["flip-crc:1", "duplicate:1", "drop:0"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        let mut injector = Injector::new(&faults, 42);
+        let mut frame = BytesMut::from(&[0x02, 0x41, 0x12, 0x34, 0x03][..]);
+        let outcome = injector.apply(&mut frame);
+        assert_eq!(frame, &[0x02, 0x41, 0xed, 0xcb, 0x03][..]);
+        assert_eq!(outcome.copies, 2);
+        assert_eq!(outcome.faults, vec!["flipped CRC", "duplicated"]);
+
+        // Frames without a footer are left alone.
+        let mut frame = BytesMut::from(&b"ab\n"[..]);
+        assert_eq!(injector.apply(&mut frame).faults, vec!["duplicated"]);
+    }
+}
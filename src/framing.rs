@@ -0,0 +1,155 @@
+use bytes::BytesMut;
+use serial_monitor::atellica::footer::FOOTER_LEN;
+use serial_monitor::atellica::header::{HEADER_LEN, STX};
+use serial_monitor::atellica::link::{ACK, NAK};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How received bytes are grouped before being displayed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameOpt {
+    /// Whatever a single read from the port returned.
+    Raw,
+    /// Up to and including a newline.
+    Line,
+    /// A fixed number of bytes.
+    Bytes(usize),
+    /// Bytes received without a gap longer than the given milliseconds.
+    Idle(u64),
+    /// Up to and including the given delimiter byte.
+    Delim(u8),
+    /// Atellica messages, from STX to the length given in their header,
+    /// with the bytes between messages as frames of their own.
+    Atellica,
+}
+
+fn parse_byte(value: &str) -> Result<u8, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid delimiter byte '{}'", value))
+}
+
+impl FromStr for FrameOpt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = match s.split_once(':') {
+            Some((kind, value)) => (kind, Some(value)),
+            None => (s, None),
+        };
+        match (kind, value) {
+            ("raw", None) => Ok(FrameOpt::Raw),
+            ("atellica", None) => Ok(FrameOpt::Atellica),
+            ("line", None) => Ok(FrameOpt::Line),
+            ("bytes", Some(value)) => match value.parse() {
+                Ok(count) if count > 0 => Ok(FrameOpt::Bytes(count)),
+                _ => Err(format!("invalid byte count '{}'", value)),
+            },
+            ("idle", Some(value)) => match value.parse() {
+                Ok(ms) if ms > 0 => Ok(FrameOpt::Idle(ms)),
+                _ => Err(format!("invalid idle time '{}'", value)),
+            },
+            ("delim", Some(value)) => Ok(FrameOpt::Delim(parse_byte(value)?)),
+            _ => Err(format!(
+                "invalid frame '{}' (expected raw, line, bytes:N, idle:ms, delim:0xNN or atellica)",
+                s
+            )),
+        }
+    }
+}
+
+// Returns the length of the next Atellica frame at the front of `src`.
+// ACK and NAK are frames of their own, so that they are seen at once.
+fn atellica_end(src: &BytesMut) -> Option<usize> {
+    if let Some(&(ACK | NAK)) = src.first() {
+        return Some(1);
+    }
+    match src.iter().position(|x| *x == STX)? {
+        0 if src.len() < 3 => None,
+        0 => match u16::from_be_bytes([src[1], src[2]]) as usize {
+            // Not a real header, skip the STX to find the next one.
+            len if len < HEADER_LEN + FOOTER_LEN => Some(1),
+            len => Some(len).filter(|len| src.len() >= *len),
+        },
+        pos => Some(pos),
+    }
+}
+
+impl FrameOpt {
+    // Returns the inter-byte gap that ends a frame, if any.
+    pub fn idle(&self) -> Option<Duration> {
+        match self {
+            FrameOpt::Idle(ms) => Some(Duration::from_millis(*ms)),
+            _ => None,
+        }
+    }
+
+    // Removes the next complete frame from the front of `src`.
+    // Idle frames are never complete here, they are ended by the caller
+    // when no byte arrives in time.
+    pub fn split(&self, src: &mut BytesMut) -> Option<BytesMut> {
+        let end = match self {
+            FrameOpt::Raw => Some(src.len()).filter(|len| *len > 0),
+            FrameOpt::Line => src.iter().position(|x| *x == b'\n').map(|pos| pos + 1),
+            FrameOpt::Bytes(count) => Some(*count).filter(|count| src.len() >= *count),
+            FrameOpt::Idle(_) => None,
+            FrameOpt::Delim(delim) => src.iter().position(|x| x == delim).map(|pos| pos + 1),
+            FrameOpt::Atellica => atellica_end(src),
+        }?;
+        Some(src.split_to(end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str_test() {
+        assert_eq!("line".parse(), Ok(FrameOpt::Line));
+        assert_eq!("bytes:18".parse(), Ok(FrameOpt::Bytes(18)));
+        assert_eq!("idle:50".parse(), Ok(FrameOpt::Idle(50)));
+        assert_eq!("delim:0x03".parse(), Ok(FrameOpt::Delim(0x03)));
+        assert_eq!("delim:10".parse(), Ok(FrameOpt::Delim(10)));
+        assert_eq!("atellica".parse(), Ok(FrameOpt::Atellica));
+        assert!("bytes:0".parse::<FrameOpt>().is_err());
+        assert!("line:1".parse::<FrameOpt>().is_err());
+    }
+
+    #[test]
+    fn split_test() {
+        let mut src = BytesMut::from(&b"ab\ncd\nef"[..]);
+        assert_eq!(FrameOpt::Line.split(&mut src).unwrap(), &b"ab\n"[..]);
+        assert_eq!(FrameOpt::Line.split(&mut src).unwrap(), &b"cd\n"[..]);
+        assert_eq!(FrameOpt::Line.split(&mut src), None);
+        assert_eq!(FrameOpt::Bytes(3).split(&mut src), None);
+        assert_eq!(FrameOpt::Delim(b'e').split(&mut src).unwrap(), &b"e"[..]);
+        assert_eq!(FrameOpt::Raw.split(&mut src).unwrap(), &b"f"[..]);
+        assert_eq!(FrameOpt::Raw.split(&mut src), None);
+    }
+
+    #[test]
+    fn atellica_split_test() {
+        let mut frame = vec![STX, 0x00, 0x16];
+        frame.resize(0x16 - 2, 0x03);
+        frame.extend([0x03, 0x03]);
+        let mut src = BytesMut::from(&[0x06][..]);
+        src.extend_from_slice(&frame);
+        src.extend_from_slice(&[STX, 0x00]);
+
+        let atellica = FrameOpt::Atellica;
+        assert_eq!(atellica.split(&mut src).unwrap(), &[0x06][..]);
+        assert_eq!(atellica.split(&mut src).unwrap(), &frame[..]);
+        assert_eq!(atellica.split(&mut src), None);
+        src.extend_from_slice(&[0x01, STX]);
+        assert_eq!(atellica.split(&mut src).unwrap(), &[STX][..]);
+
+        let mut src = BytesMut::from(&[NAK][..]);
+        assert_eq!(atellica.split(&mut src).unwrap(), &[NAK][..]);
+    }
+}
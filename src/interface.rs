@@ -1,7 +1,9 @@
 pub mod ports {
     use crate::error::ProgramError;
     use crate::opt::Opt;
+    use crate::rfc2217;
     use regex_lite::Regex;
+    use std::path::Path;
     use std::str;
     use tokio_serial::{SerialPortInfo, SerialPortType, UsbPortInfo};
 
@@ -24,7 +26,7 @@ pub mod ports {
     #[cfg(target_os = "macos")]
     fn available_ports() -> Result<Vec<SerialPortInfo>, ProgramError> {
         Ok(tokio_serial::available_ports()
-            .map_err(|x| ProgramError::SerialPortError(x))?
+            .map_err(ProgramError::SerialPortError)?
             .into_iter()
             .map(|mut port| {
                 port.port_name = map_port_name(&port.port_name);
@@ -36,7 +38,7 @@ pub mod ports {
     // Returns a list of the available ports (for everything but macos)
     #[cfg(not(target_os = "macos"))]
     fn available_ports() -> Result<Vec<SerialPortInfo>, ProgramError> {
-        Ok(tokio_serial::available_ports().map_err(|e| ProgramError::SerialPortError(e))?)
+        tokio_serial::available_ports().map_err(ProgramError::SerialPortError)
     }
 
     // Checks to see if a string matches a pattern used for filtering.
@@ -97,11 +99,28 @@ pub mod ports {
     }
 
     fn filtered_ports(opt: &Opt) -> Result<Vec<SerialPortInfo>, ProgramError> {
+        // Remote ports can't be enumerated, so the URL is taken as it is.
+        if let Some(url) = opt.port.as_ref().filter(|x| rfc2217::address(x).is_some()) {
+            return Ok(vec![SerialPortInfo {
+                port_name: url.clone(),
+                port_type: SerialPortType::Unknown,
+            }]);
+        }
         let mut ports: Vec<SerialPortInfo> = available_ports()?
             .into_iter()
-            .filter(|info| usb_port_matches(&info, opt))
+            .filter(|info| usb_port_matches(info, opt))
             .collect();
         ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+        // Ports that are not enumerated, such as pseudo terminals, can still
+        // be opened by giving their path.
+        if let Some(path) = opt.port.as_ref().filter(|x| Path::new(x).exists()) {
+            if ports.is_empty() {
+                ports.push(SerialPortInfo {
+                    port_name: path.clone(),
+                    port_type: SerialPortType::Unknown,
+                });
+            }
+        }
         if let Some(index) = opt.index {
             if index < ports.len() {
                 Ok(vec![ports[index].clone()])
@@ -147,7 +166,7 @@ pub mod ports {
             if let SerialPortType::UsbPort(info) = &port.port_type {
                 println!(
                     "USB Serial Device{} found @{}",
-                    extra_usb_info(&info),
+                    extra_usb_info(info),
                     port.port_name
                 );
             } else {
@@ -162,11 +181,34 @@ pub mod ports {
         Ok(filtered_port(opt)?.port_name)
     }
 
-    // Returns the first port which matches the filtering criteria.
-    pub fn find_ports(opt: &Opt) -> Result<Vec<String>, ProgramError> {
-        Ok(filtered_ports(opt)?
-            .iter()
-            .map(|x| x.port_name.clone())
-            .collect())
+    // Returns all of the ports which match the filtering criteria.
+    pub fn find_ports(opt: &Opt) -> Result<Vec<SerialPortInfo>, ProgramError> {
+        filtered_ports(opt)
+    }
+}
+
+pub mod serial {
+    use crate::error::ProgramError;
+    use crate::opt::{DataBitsOpt, Opt, StopBitsOpt};
+    use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+    // Opens a port with the serial settings given on the command line.
+    pub fn open(path: &str, opt: &Opt) -> Result<SerialStream, ProgramError> {
+        let data_bits = DataBitsOpt::try_from(opt.databits).map_err(ProgramError::IoError)?;
+        let stop_bits = StopBitsOpt::try_from(opt.stopbits).map_err(ProgramError::IoError)?;
+
+        let mut port = tokio_serial::new(path, opt.baud)
+            .data_bits(data_bits.0)
+            .stop_bits(stop_bits.0)
+            .parity(opt.parity.into())
+            .flow_control(opt.flow.into())
+            .open_native_async()
+            .map_err(|x| ProgramError::UnableToOpen(path.to_string(), x))?;
+
+        #[cfg(unix)]
+        port.set_exclusive(false)
+            .expect("Unable to set serial port exclusive to false");
+
+        Ok(port)
     }
 }
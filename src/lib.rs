@@ -0,0 +1,4 @@
+//! Encoder and decoder of the Atellica automation interface protocol, as
+//! used by the `serial_monitor` binary.
+
+pub mod atellica;
@@ -1,36 +1,78 @@
 use codec::Codec;
+use control::Control;
 use error::ProgramError;
-use futures::StreamExt;
-use interface::ports;
-use opt::Opt;
+use interface::{ports, serial};
+use monitor::Monitor;
+use opt::{AutoBaudOpt, Command, Opt};
 use std::process::exit;
 use structopt::StructOpt;
-use tokio_serial::SerialPortBuilderExt;
+use tokio::sync::broadcast;
+use tokio_serial::SerialPortInfo;
 use tokio_util::codec::Decoder;
 
-mod atellica;
-
+mod autobaud;
+mod bridge;
 mod codec;
+mod color;
+mod compose;
+mod config;
+mod control;
+mod decode;
+mod encode;
 mod error;
+mod fault;
+mod framing;
 mod interface;
+mod modem;
+mod monitor;
 mod opt;
+mod port_settings;
+mod proxy;
+#[cfg(unix)]
+mod pty;
+mod relay;
+mod rfc2217;
+mod send;
+mod timestamp;
+mod trigger;
 
-fn handle_request(path: &String, opt: &Opt) -> tokio::task::JoinHandle<()> {
-    let mut port = match tokio_serial::new(path, opt.baud).open_native_async() {
-        Err(x) => panic!("Error: {:?}", x),
-        Ok(x) => x,
-    };
+async fn handle_request(
+    path: &str,
+    opt: &Opt,
+    controls: Option<broadcast::Receiver<Control>>,
+) -> Result<tokio::task::JoinHandle<Option<i32>>, ProgramError> {
+    if rfc2217::address(path).is_some() {
+        let client = rfc2217::Client::connect(path, opt, controls).await?;
+        return Ok(tokio::task::spawn(client.run()));
+    }
 
-    #[cfg(unix)]
-    port.set_exclusive(false)
-        .expect("Unable to set serial port exclusive to false");
+    let port = serial::open(path, opt)?;
 
-    let mut reader = Codec::new(path.clone(), &opt).framed(port);
-    tokio::task::spawn(async move {
-        loop {
-            reader.next().await;
-        }
-    })
+    let reader = Codec::new(path.to_string(), opt)
+        .with_link(opt.link_settings())
+        .framed(port);
+    Ok(tokio::task::spawn(
+        Monitor::new(reader, opt, controls).run(),
+    ))
+}
+
+// Returns the options for the index'th port found, with its own settings applied.
+fn port_opt(opt: &Opt, index: usize, info: &SerialPortInfo) -> Opt {
+    let mut port_opt = opt.clone();
+    if let Some(settings) = opt.port_settings.iter().find(|x| x.matches(index, info)) {
+        settings.apply(&mut port_opt);
+    }
+    port_opt
+}
+
+// Exits with the code of --trigger-exit if a trigger match stopped the
+// command, once it closed the port.
+fn exit_on_trigger(result: Result<Option<i32>, ProgramError>) -> Result<(), ProgramError> {
+    if let Some(code) = result? {
+        control::restore_terminal();
+        exit(code);
+    }
+    Ok(())
 }
 
 fn handle_opt(opt: &Opt) -> Result<(), ProgramError> {
@@ -38,13 +80,19 @@ fn handle_opt(opt: &Opt) -> Result<(), ProgramError> {
         println!("{:#?}", opt);
     }
 
+    if let Some(format) = &opt.timestamp_format {
+        if !timestamp::is_valid_format(format) {
+            return Err(ProgramError::InvalidTimestampFormat(format.clone()));
+        }
+    }
+
     if opt.list {
-        ports::list_ports(&opt)?;
+        ports::list_ports(opt)?;
         exit(0);
     }
 
     if opt.find {
-        println!("{}", ports::find_first_port(&opt)?);
+        println!("{}", ports::find_first_port(opt)?);
         exit(0);
     }
 
@@ -53,17 +101,94 @@ fn handle_opt(opt: &Opt) -> Result<(), ProgramError> {
 
 #[tokio::main]
 async fn main() -> Result<(), ProgramError> {
-    let opt = Opt::from_args();
+    let matches = Opt::clap().get_matches();
+    let mut opt = Opt::from_clap(&matches);
+    config::apply_profile(&mut opt, &matches)?;
 
     handle_opt(&opt)?;
 
-    let tasks: Vec<_> = ports::find_ports(&opt)
-        .unwrap()
-        .into_iter()
-        .map(|f| handle_request(&f, &opt))
-        .collect();
+    if let Some(Command::Decode(decode)) = &opt.cmd {
+        return decode::run(&opt, decode);
+    }
+
+    if let Some(Command::Encode(encode)) = &opt.cmd {
+        return encode::run(encode);
+    }
+
+    let (controls, _terminal) = match opt.interactive {
+        true => {
+            let (sender, _) = broadcast::channel(16);
+            let terminal = control::spawn_keys(sender.clone());
+            (Some(sender), Some(terminal))
+        }
+        false => (None, None),
+    };
+
+    if let Some(Command::Serve(serve)) = &opt.cmd {
+        let info = ports::find_ports(&opt)?.remove(0);
+        return exit_on_trigger(
+            bridge::serve(&info.port_name, &port_opt(&opt, 0, &info), serve).await,
+        );
+    }
+
+    if let Some(Command::Proxy(proxy)) = &opt.cmd {
+        let info = ports::find_ports(&opt)?.remove(0);
+        return exit_on_trigger(
+            proxy::run(&info.port_name, &port_opt(&opt, 0, &info), proxy).await,
+        );
+    }
+
+    if let Some(Command::Send(send)) = &opt.cmd {
+        let info = ports::find_ports(&opt)?.remove(0);
+        return exit_on_trigger(send::run(&info.port_name, &port_opt(&opt, 0, &info), send).await);
+    }
 
-    futures::future::join_all(tasks).await;
+    #[cfg(unix)]
+    if let Some(Command::Pty(pty)) = &opt.cmd {
+        if pty.loopback {
+            return exit_on_trigger(pty::run(None, &opt, pty).await);
+        }
+        let info = ports::find_ports(&opt)?.remove(0);
+        return exit_on_trigger(
+            pty::run(Some(&info.port_name), &port_opt(&opt, 0, &info), pty).await,
+        );
+    }
+    #[cfg(not(unix))]
+    if let Some(Command::Pty(_)) = &opt.cmd {
+        return Err(ProgramError::IoError(std::io::Error::other(
+            "pseudo terminals are only available on unix",
+        )));
+    }
+
+    let mut tasks = Vec::new();
+    for (index, info) in ports::find_ports(&opt)?.into_iter().enumerate() {
+        let mut port_opt = port_opt(&opt, index, &info);
+
+        if let Some(mode) = opt.auto_baud {
+            let best = autobaud::detect(&info.port_name, &port_opt).await?;
+            match (mode, best) {
+                (AutoBaudOpt::Report, _) => continue,
+                (AutoBaudOpt::Adopt, Some(best)) => best.apply(&mut port_opt),
+                (AutoBaudOpt::Adopt, None) => {}
+            }
+        }
+
+        tasks.push(
+            handle_request(
+                &info.port_name,
+                &port_opt,
+                controls.as_ref().map(|x| x.subscribe()),
+            )
+            .await?,
+        );
+    }
+
+    // A trigger match on any port ends the whole program.
+    while !tasks.is_empty() {
+        let (code, _, rest) = futures::future::select_all(tasks).await;
+        exit_on_trigger(Ok(code.ok().flatten()))?;
+        tasks = rest;
+    }
 
     Ok(())
 }
@@ -0,0 +1,110 @@
+use std::fmt;
+use tokio_serial::{SerialPort, SerialStream};
+
+// State of the modem status lines of a port.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ModemLines {
+    cts: bool,
+    dsr: bool,
+    dcd: bool,
+    ri: bool,
+}
+
+fn level(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+impl ModemLines {
+    pub fn read(port: &mut SerialStream) -> tokio_serial::Result<Self> {
+        Ok(Self {
+            cts: port.read_clear_to_send()?,
+            dsr: port.read_data_set_ready()?,
+            dcd: port.read_carrier_detect()?,
+            ri: port.read_ring_indicator()?,
+        })
+    }
+
+    // Decodes the modem state sent by an RFC 2217 server.
+    pub fn from_state(state: u8) -> Self {
+        Self {
+            cts: state & 0x10 != 0,
+            dsr: state & 0x20 != 0,
+            ri: state & 0x40 != 0,
+            dcd: state & 0x80 != 0,
+        }
+    }
+
+    // Encodes the lines as an RFC 2217 modem state, with the delta bits
+    // set for the lines that changed since `previous`.
+    pub fn state(&self, previous: &Self) -> u8 {
+        let bits = [
+            (self.cts, 0x10),
+            (self.dsr, 0x20),
+            (self.ri, 0x40),
+            (self.dcd, 0x80),
+            (self.cts != previous.cts, 0x01),
+            (self.dsr != previous.dsr, 0x02),
+            // RI only reports its trailing edge.
+            (previous.ri && !self.ri, 0x04),
+            (self.dcd != previous.dcd, 0x08),
+        ];
+        bits.iter().filter(|(on, _)| *on).map(|(_, bit)| bit).sum()
+    }
+
+    fn lines(&self) -> [(&'static str, bool); 4] {
+        [
+            ("CTS", self.cts),
+            ("DSR", self.dsr),
+            ("DCD", self.dcd),
+            ("RI", self.ri),
+        ]
+    }
+
+    // Describes the lines that changed since `previous`, e.g. "CTS off -> on".
+    pub fn changes(&self, previous: &Self) -> Vec<String> {
+        self.lines()
+            .iter()
+            .zip(previous.lines())
+            .filter(|((_, now), (_, before))| now != before)
+            .map(|((name, now), (_, before))| {
+                format!("{} {} -> {}", name, level(before), level(*now))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for ModemLines {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self
+            .lines()
+            .iter()
+            .map(|(name, on)| format!("{}={}", name, level(*on)))
+            .collect();
+        write!(f, "{}", lines.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_test() {
+        let before = ModemLines::default();
+        let now = ModemLines {
+            cts: true,
+            ri: true,
+            ..Default::default()
+        };
+        assert_eq!(now.changes(&before), vec!["CTS off -> on", "RI off -> on"]);
+        assert!(now.changes(&now).is_empty());
+        assert_eq!(now.to_string(), "CTS=on DSR=off DCD=off RI=on");
+        assert_eq!(now.state(&before), 0x51);
+        assert_eq!(before.state(&now), 0x05);
+        assert_eq!(ModemLines::from_state(0x51), now);
+    }
+}
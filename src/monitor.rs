@@ -0,0 +1,265 @@
+use futures::StreamExt;
+use std::future;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{self, Instant, Interval};
+use tokio_serial::{SerialPort, SerialStream};
+use tokio_util::codec::Framed;
+
+use crate::codec::Codec;
+use crate::control::Control;
+use crate::modem::ModemLines;
+use crate::opt::{LevelOpt, Opt};
+
+// Length of a BREAK sent from the keyboard when --break-ms is not given.
+const DEFAULT_BREAK_MS: u64 = 250;
+
+// Displays everything that happens on one open port.
+pub struct Monitor {
+    reader: Framed<SerialStream, Codec>,
+    modem_poll: Option<Interval>,
+    modem_lines: ModemLines,
+    controls: Option<broadcast::Receiver<Control>>,
+    // Most ports assert DTR and RTS when they are opened.
+    dtr: bool,
+    rts: bool,
+    startup_dtr: Option<LevelOpt>,
+    startup_rts: Option<LevelOpt>,
+    break_ms: Option<u64>,
+}
+
+impl Monitor {
+    pub fn new(
+        reader: Framed<SerialStream, Codec>,
+        opt: &Opt,
+        controls: Option<broadcast::Receiver<Control>>,
+    ) -> Self {
+        Self {
+            reader,
+            modem_poll: opt
+                .modem_lines
+                .then(|| time::interval(Duration::from_millis(opt.modem_poll_ms))),
+            modem_lines: ModemLines::default(),
+            controls,
+            dtr: true,
+            rts: true,
+            startup_dtr: opt.dtr,
+            startup_rts: opt.rts,
+            break_ms: opt.break_ms,
+        }
+    }
+
+    // Runs until the port is closed, or until a trigger match stops it.
+    // Returns the code to exit with in that case.
+    pub async fn run(mut self) -> Option<i32> {
+        if let Some(level) = self.startup_dtr {
+            self.set_dtr(level.into());
+        }
+        if let Some(level) = self.startup_rts {
+            self.set_rts(level.into());
+        }
+        if let Some(ms) = self.break_ms {
+            self.send_break(ms).await;
+        }
+        if self.modem_poll.is_some() {
+            self.start_modem_lines();
+        }
+
+        while self.reader.codec().exit_code().is_none() {
+            let link_deadline = self.reader.codec().link_deadline().map(Into::into);
+            let idle_deadline = self.reader.codec().idle_deadline();
+            tokio::select! {
+                open = Self::read(&mut self.reader) => {
+                    if !open {
+                        break;
+                    }
+                    self.write_link().await;
+                }
+                _ = Self::sleep_until(link_deadline) => {
+                    self.reader.codec_mut().poll_link();
+                    self.write_link().await;
+                }
+                _ = Self::sleep_until(idle_deadline) => Self::end_idle_frame(&mut self.reader),
+                _ = Self::tick(&mut self.modem_poll) => self.poll_modem_lines(),
+                control = Self::control(&mut self.controls) => match control {
+                    Ok(Control::ToggleDtr) => self.set_dtr(!self.dtr),
+                    Ok(Control::ToggleRts) => self.set_rts(!self.rts),
+                    Ok(Control::Break) => {
+                        self.send_break(self.break_ms.unwrap_or(DEFAULT_BREAK_MS)).await
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => self.controls = None,
+                },
+            }
+        }
+        self.reader.codec().exit_code()
+    }
+
+    // Reads from the port and displays what arrived. Returns false once the
+    // port is closed.
+    async fn read<T: AsyncRead + Unpin>(reader: &mut Framed<T, Codec>) -> bool {
+        let next = reader.next().await;
+        match next {
+            None => false,
+            Some(Err(err)) => {
+                reader.codec_mut().error(&err);
+                true
+            }
+            Some(Ok(_)) => true,
+        }
+    }
+
+    async fn sleep_until(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => time::sleep_until(deadline).await,
+            None => future::pending().await,
+        }
+    }
+
+    // Displays the bytes waiting in the read buffer once the port stayed
+    // quiet for the idle time after the last of them.
+    fn end_idle_frame<T>(reader: &mut Framed<T, Codec>) {
+        let mut pending = reader.read_buffer_mut().split();
+        reader.codec_mut().flush(&mut pending);
+    }
+
+    // Writes the answers of the link layer to the port.
+    async fn write_link(&mut self) {
+        for bytes in self.reader.codec_mut().take_outgoing() {
+            if let Err(err) = self.reader.get_mut().write_all(&bytes).await {
+                self.reader.codec_mut().error(&err);
+            }
+        }
+    }
+
+    async fn tick(interval: &mut Option<Interval>) {
+        match interval {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => future::pending().await,
+        }
+    }
+
+    async fn control(
+        controls: &mut Option<broadcast::Receiver<Control>>,
+    ) -> Result<Control, RecvError> {
+        match controls {
+            Some(controls) => controls.recv().await,
+            None => future::pending().await,
+        }
+    }
+
+    fn set_dtr(&mut self, level: bool) {
+        match self.reader.get_mut().write_data_terminal_ready(level) {
+            Ok(()) => {
+                self.dtr = level;
+                self.reader
+                    .codec_mut()
+                    .event(&format!("DTR {}", if level { "on" } else { "off" }));
+            }
+            Err(err) => self.reader.codec_mut().error(&err.into()),
+        }
+    }
+
+    fn set_rts(&mut self, level: bool) {
+        match self.reader.get_mut().write_request_to_send(level) {
+            Ok(()) => {
+                self.rts = level;
+                self.reader
+                    .codec_mut()
+                    .event(&format!("RTS {}", if level { "on" } else { "off" }));
+            }
+            Err(err) => self.reader.codec_mut().error(&err.into()),
+        }
+    }
+
+    async fn send_break(&mut self, ms: u64) {
+        if let Err(err) = self.reader.get_ref().set_break() {
+            self.reader.codec_mut().error(&err.into());
+            return;
+        }
+        time::sleep(Duration::from_millis(ms)).await;
+        match self.reader.get_ref().clear_break() {
+            Ok(()) => self.reader.codec_mut().event(&format!("BREAK {} ms", ms)),
+            Err(err) => self.reader.codec_mut().error(&err.into()),
+        }
+    }
+
+    fn start_modem_lines(&mut self) {
+        match ModemLines::read(self.reader.get_mut()) {
+            Ok(lines) => {
+                self.modem_lines = lines;
+                self.reader.codec_mut().event(&format!("modem {}", lines));
+            }
+            Err(err) => self.stop_modem_lines(err),
+        }
+    }
+
+    fn poll_modem_lines(&mut self) {
+        match ModemLines::read(self.reader.get_mut()) {
+            Ok(lines) => {
+                for change in lines.changes(&self.modem_lines) {
+                    self.reader.codec_mut().event(&format!("modem {}", change));
+                }
+                self.modem_lines = lines;
+            }
+            Err(err) => self.stop_modem_lines(err),
+        }
+    }
+
+    // Reports why the modem lines cannot be read and stops polling them.
+    fn stop_modem_lines(&mut self, err: tokio_serial::Error) {
+        self.modem_poll = None;
+        self.reader.codec_mut().error(&err.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+    use tokio::io::DuplexStream;
+
+    // Runs the reading part of the monitor loop for `ms` milliseconds, with
+    // a timer ticking more often than the idle time.
+    async fn run_for(reader: &mut Framed<DuplexStream, Codec>, ms: u64) {
+        let end = Instant::now() + Duration::from_millis(ms);
+        let mut tick = time::interval(Duration::from_millis(10));
+        while Instant::now() < end {
+            let idle_deadline = reader.codec().idle_deadline();
+            tokio::select! {
+                _ = Monitor::read(reader) => {}
+                _ = Monitor::sleep_until(idle_deadline) => Monitor::end_idle_frame(reader),
+                _ = tick.tick() => {}
+                _ = time::sleep_until(end) => {}
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_frame_test() {
+        let opt = Opt::from_iter(["serial-monitor", "--frame", "idle:50"]);
+        let (port, mut peer) = tokio::io::duplex(64);
+        let mut reader = Framed::new(port, Codec::new("test".to_string(), &opt));
+
+        // Bytes 30 ms apart belong to the same frame however long it gets.
+        let writer = tokio::spawn(async move {
+            for byte in 0..8 {
+                peer.write_all(&[byte]).await.unwrap();
+                time::sleep(Duration::from_millis(30)).await;
+            }
+            peer
+        });
+        run_for(&mut reader, 230).await;
+        assert_eq!(reader.read_buffer().len(), 8);
+        assert!(reader.codec().idle_deadline().is_some());
+
+        // The frame ends once the port stayed quiet for 50 ms.
+        let _peer = writer.await.unwrap();
+        run_for(&mut reader, 40).await;
+        assert!(reader.read_buffer().is_empty());
+        assert_eq!(reader.codec().idle_deadline(), None);
+    }
+}
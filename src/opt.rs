@@ -1,12 +1,31 @@
+use serial_monitor::atellica::enums::InterfacePositionIndex;
+use serial_monitor::atellica::LinkSettings;
 use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::result::Result as StdResult;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio_serial::{DataBits, FlowControl, Parity, StopBits};
 
+use crate::compose::{self, MessageOpt};
+use crate::fault::Fault;
+use crate::framing::FrameOpt;
+use crate::port_settings::PortSettings;
+use crate::trigger::{parse_hex, Trigger};
+
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(name = "serial-monitor")]
 pub struct Opt {
-    /// Filter based on name of port
+    /// Named profile from the configuration files to take settings from
+    #[structopt(long)]
+    pub profile: Option<String>,
+
+    /// Configuration file read after the per-user and per-project ones
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Filter based on name of port, or an rfc2217://host:port URL
     #[structopt(short, long)]
     pub port: Option<String>,
 
@@ -71,12 +90,266 @@ pub struct Opt {
     #[structopt(long, default_value = "8")]
     pub databits: usize,
 
-    /// Byte codec (Hex, Decimal, Char)
+    /// Detect the baud rate before monitoring, then report the best
+    /// candidate and exit, or adopt it (report, adopt)
+    #[structopt(long)]
+    pub auto_baud: Option<AutoBaudOpt>,
+
+    /// How long to listen at each candidate rate, in milliseconds
+    #[structopt(long, default_value = "1000")]
+    pub auto_baud_ms: u64,
+
+    /// Also try 7 data bits and even or odd parity at each rate
+    #[structopt(long)]
+    pub auto_baud_combos: bool,
+
+    /// Byte codec (Hex, Decimal, Char, Dump, Atellica)
     #[structopt(long, default_value = "hex")]
     pub codec: CodecOpt,
+
+    /// Name shown at the start of each line instead of the port name
+    #[structopt(long)]
+    pub label: Option<String>,
+
+    /// Settings for a single port, selected by name, serial or index, e.g.
+    /// "serial=A1B2,baud=115200,parity=even,frame=line,codec=char,label=console"
+    #[structopt(long, number_of_values = 1)]
+    pub port_settings: Vec<PortSettings>,
+
+    /// How bytes are grouped into lines (raw, line, bytes:N, idle:ms, delim:0xNN, atellica)
+    #[structopt(long, default_value = "raw")]
+    pub frame: FrameOpt,
+
+    /// Report skipped, duplicated or out of order Atellica sequence ids;
+    /// frames are split as Atellica messages
+    #[structopt(long)]
+    pub check_sequence: bool,
+
+    /// Answer received Atellica frames with ACK or NAK and retransmit sent
+    /// ones until they are acknowledged; frames are split as Atellica
+    /// messages. Frames with a bad CRC are ACKed unless --link-check-crc is
+    /// given
+    #[structopt(long)]
+    pub link: bool,
+
+    /// How long a sent frame waits for ACK or NAK, in milliseconds
+    #[structopt(long, default_value = "1000")]
+    pub link_timeout_ms: u64,
+
+    /// Retransmissions of a frame before giving up on it
+    #[structopt(long, default_value = "3")]
+    pub link_retries: u32,
+
+    /// Answer frames with a bad CRC with NAK; the CRC is assumed to be
+    /// CRC-16/CCITT-FALSE, so mismatches are ignored otherwise
+    #[structopt(long)]
+    pub link_check_crc: bool,
+
+    /// Pattern to watch for: hex bytes ("02 41 03") or, prefixed with "re:", a
+    /// regex over the escaped char view ("re:ERR\\x1b")
+    #[structopt(long, number_of_values = 1)]
+    pub trigger: Vec<Trigger>,
+
+    /// Only show the N lines before and after each trigger match
+    #[structopt(long)]
+    pub trigger_context: Option<usize>,
+
+    /// Shell command run on each trigger match
+    #[structopt(long)]
+    pub trigger_exec: Option<String>,
+
+    /// Exit with this code on the first trigger match
+    #[structopt(long)]
+    pub trigger_exit: Option<i32>,
+
+    /// Report changes of the CTS, DSR, DCD and RI modem lines
+    #[structopt(long)]
+    pub modem_lines: bool,
+
+    /// How often the modem lines are polled, in milliseconds
+    #[structopt(long, default_value = "50")]
+    pub modem_poll_ms: u64,
+
+    /// Set DTR after opening the port (on, off)
+    #[structopt(long)]
+    pub dtr: Option<LevelOpt>,
+
+    /// Set RTS after opening the port (on, off)
+    #[structopt(long)]
+    pub rts: Option<LevelOpt>,
+
+    /// Send a BREAK of this many milliseconds after opening the port
+    #[structopt(long)]
+    pub break_ms: Option<u64>,
+
+    /// Toggle DTR and RTS and send BREAK with the d, r and b keys
+    #[structopt(short, long)]
+    pub interactive: bool,
+
+    /// When to color the output (auto, always, never)
+    #[structopt(long, default_value = "auto")]
+    pub color: ColorOpt,
+
+    /// Timestamp printed on each line (time, local, utc, session, delta)
+    #[structopt(long, default_value = "time")]
+    pub timestamp: TimestampOpt,
+
+    /// Custom strftime format used by the time, local and utc timestamps
+    #[structopt(long)]
+    pub timestamp_format: Option<String>,
+
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum Command {
+    /// Share the port with TCP clients while monitoring it
+    Serve(ServeOpt),
+    /// Create a pseudo terminal for an application and relay it to the port
+    Pty(PtyOpt),
+    /// Forward traffic between the port and a peer port, injecting faults
+    Proxy(ProxyOpt),
+    /// Send frames or messages to the port and display the answers
+    Send(SendOpt),
+    /// Decode the Atellica frames of a hex dump, without opening a port
+    Decode(DecodeOpt),
+    /// Print the frames of Atellica messages, without opening a port
+    Encode(EncodeOpt),
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct ServeOpt {
+    /// Address to accept TCP clients on
+    #[structopt(long, default_value = "127.0.0.1:7000")]
+    pub listen: SocketAddr,
+
+    /// Which clients may write to the port (none, single, multi)
+    #[structopt(long, default_value = "single")]
+    pub writers: WritersOpt,
+
+    /// Speak RFC 2217 so that clients can change the port settings
+    #[structopt(long)]
+    pub rfc2217: bool,
 }
 
-struct DataBitsOpt(DataBits);
+#[derive(StructOpt, Debug, Clone)]
+pub struct PtyOpt {
+    /// Also make the pseudo terminal available at this path
+    #[structopt(long, parse(from_os_str))]
+    pub link: Option<PathBuf>,
+
+    /// Echo what the application writes instead of relaying it to a port
+    #[structopt(long)]
+    pub loopback: bool,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct ProxyOpt {
+    /// Port to forward the traffic of the port to
+    #[structopt(long)]
+    pub peer: String,
+
+    /// Baud rate of the peer port, when it differs from the port's
+    #[structopt(long)]
+    pub peer_baud: Option<u32>,
+
+    /// Fault injected into a share of the frames, e.g. drop:0.1,
+    /// corrupt:0.05, flip-crc:0.1, delay:500:0.2 or duplicate:0.1
+    #[structopt(long = "fault", number_of_values = 1)]
+    pub faults: Vec<Fault>,
+
+    /// Port whose frames get the faults (both, port, peer)
+    #[structopt(long, default_value = "both")]
+    pub fault_from: FaultSideOpt,
+
+    /// Seed of the random fault choices, to repeat a run
+    #[structopt(long)]
+    pub seed: Option<u64>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct SendOpt {
+    /// Frames to send, in hex ("02 00 15 ..."), one after the other
+    #[structopt(required_unless = "messages", parse(try_from_str = parse_hex))]
+    pub frames: Vec<Vec<u8>>,
+
+    #[structopt(flatten)]
+    pub message: MessageArgs,
+
+    /// How long to keep displaying answers after the last frame, in milliseconds
+    #[structopt(long, default_value = "1000")]
+    pub wait_ms: u64,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct DecodeOpt {
+    /// File holding the hex dump, or the output of the monitor with the hex
+    /// codec; stdin when left out or "-"
+    #[structopt(parse(from_os_str))]
+    pub file: Option<PathBuf>,
+
+    /// The input holds the bytes themselves rather than hex
+    #[structopt(long)]
+    pub raw: bool,
+
+    /// Report frames with a bad CRC; the CRC is assumed to be
+    /// CRC-16/CCITT-FALSE
+    #[structopt(long)]
+    pub check_crc: bool,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct EncodeOpt {
+    /// TOML file, or JSON file when named *.json, with a list of messages
+    /// under "message"; they come before the ones given with --message
+    #[structopt(long, required_unless = "messages", parse(from_os_str))]
+    pub file: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    pub message: MessageArgs,
+
+    /// How the frames are printed (hex, dec, c)
+    #[structopt(long, default_value = "hex")]
+    pub format: EncodeFormatOpt,
+}
+
+// Atellica messages described on the command line.
+#[derive(StructOpt, Debug, Clone)]
+pub struct MessageArgs {
+    /// Message built from the options below: add-queue:SAMPLE_ID
+    #[structopt(long = "message", number_of_values = 1)]
+    pub messages: Vec<MessageOpt>,
+
+    /// Instrument the messages are addressed to
+    #[structopt(long, default_value = "1")]
+    pub instrument: u8,
+
+    /// Interface position of the messages (0, 1)
+    #[structopt(long, default_value = "0", parse(try_from_str = compose::parse_ip))]
+    pub ip: InterfacePositionIndex,
+
+    /// Give the tubes of add-queue messages stat priority
+    #[structopt(long)]
+    pub stat: bool,
+
+    /// Truncate sample ids longer than 255 characters instead of refusing them
+    #[structopt(long)]
+    pub truncate_sample_ids: bool,
+}
+
+impl Opt {
+    // Returns the link layer settings, if --link is on.
+    pub fn link_settings(&self) -> Option<LinkSettings> {
+        self.link.then(|| LinkSettings {
+            timeout: Duration::from_millis(self.link_timeout_ms),
+            retries: self.link_retries,
+            check_crc: self.link_check_crc,
+        })
+    }
+}
+
+pub struct DataBitsOpt(pub DataBits);
 
 impl TryFrom<usize> for DataBitsOpt {
     type Error = io::Error;
@@ -87,18 +360,15 @@ impl TryFrom<usize> for DataBitsOpt {
             6 => Ok(Self(DataBits::Six)),
             7 => Ok(Self(DataBits::Seven)),
             8 => Ok(Self(DataBits::Eight)),
-            _ => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "databits out of range",
-            )),
+            _ => Err(io::Error::other("databits out of range")),
         }
     }
 }
 
 /// Flow control modes
-#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString)]
+#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString, PartialEq)]
 #[strum(serialize_all = "snake_case")]
-enum FlowControlOpt {
+pub enum FlowControlOpt {
     /// No flow control.
     None,
     /// Flow control using XON/XOFF bytes.
@@ -123,11 +393,102 @@ pub enum CodecOpt {
     Hex,
     Dec,
     Char,
+    /// Offset, hex and ASCII columns like `hexdump -C`.
+    Dump,
+    /// Fields of Atellica messages, one frame at a time.
+    Atellica,
 }
 
-#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString)]
+/// Output formats of the encode command
+#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum EncodeFormatOpt {
+    /// Hex bytes, as read back by decode and send.
+    Hex,
+    /// Decimal bytes.
+    Dec,
+    /// A C array for each frame, behind a comment describing the message.
+    C,
+}
+
+/// What to do with the detected baud rate
+#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum AutoBaudOpt {
+    /// Print the scores of all candidates and exit.
+    Report,
+    /// Monitor the port with the best candidate.
+    Adopt,
+}
+
+/// Write access of TCP clients
+#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum WritersOpt {
+    /// Clients can only read from the port.
+    None,
+    /// The first client to send data writes to the port until it disconnects.
+    Single,
+    /// Every client writes to the port.
+    Multi,
+}
+
+/// Sides of a proxy
+#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum FaultSideOpt {
+    Both,
+    /// The port found with the usual filters.
+    Port,
+    /// The port given with --peer.
+    Peer,
+}
+
+/// Control line levels
+#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum LevelOpt {
+    On,
+    Off,
+}
+
+impl From<LevelOpt> for bool {
+    fn from(opt: LevelOpt) -> Self {
+        opt == LevelOpt::On
+    }
+}
+
+/// Color modes
+#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum ColorOpt {
+    /// Color when stdout is a terminal and NO_COLOR is not set.
+    Auto,
+    /// Always color.
+    Always,
+    /// Never color.
+    Never,
+}
+
+/// Timestamp modes
+#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum TimestampOpt {
+    /// Local wall-clock time, without the date.
+    Time,
+    /// Local date and time.
+    Local,
+    /// UTC date and time.
+    Utc,
+    /// Time elapsed since the port was opened.
+    Session,
+    /// Time elapsed since the previous chunk read from the same port.
+    Delta,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt, strum::EnumString, PartialEq)]
 #[strum(serialize_all = "snake_case")]
-enum ParityOpt {
+pub enum ParityOpt {
     /// No parity bit.
     None,
     /// Parity bit sets odd number of 1 bits.
@@ -146,7 +507,7 @@ impl From<ParityOpt> for Parity {
     }
 }
 
-struct StopBitsOpt(StopBits);
+pub struct StopBitsOpt(pub StopBits);
 
 impl TryFrom<usize> for StopBitsOpt {
     type Error = io::Error;
@@ -155,10 +516,7 @@ impl TryFrom<usize> for StopBitsOpt {
         match value {
             1 => Ok(Self(StopBits::One)),
             2 => Ok(Self(StopBits::Two)),
-            _ => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "stopbits out of range",
-            )),
+            _ => Err(io::Error::other("stopbits out of range")),
         }
     }
 }
@@ -0,0 +1,169 @@
+use std::path::Path;
+use std::str::FromStr;
+use tokio_serial::SerialPortInfo;
+
+use crate::framing::FrameOpt;
+use crate::opt::{CodecOpt, FlowControlOpt, Opt, ParityOpt};
+
+// Picks the port that some settings apply to.
+#[derive(Clone, Debug, PartialEq)]
+enum Selector {
+    Name(String),
+    Serial(String),
+    Index(usize),
+}
+
+/// Settings that replace the global ones for a single port
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortSettings {
+    selector: Selector,
+    baud: Option<u32>,
+    parity: Option<ParityOpt>,
+    stopbits: Option<usize>,
+    flow: Option<FlowControlOpt>,
+    databits: Option<usize>,
+    frame: Option<FrameOpt>,
+    codec: Option<CodecOpt>,
+    label: Option<String>,
+}
+
+fn parse<T>(key: &str, value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: ToString,
+{
+    value
+        .parse()
+        .map_err(|e: T::Err| format!("{} '{}': {}", key, value, e.to_string()))
+}
+
+impl PortSettings {
+    // Builds the settings from key/value pairs, exactly one of which must be
+    // a selector (name, serial or index).
+    pub fn from_pairs<'a>(
+        pairs: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, String> {
+        let mut selector = None;
+        let mut settings = Self {
+            selector: Selector::Index(0),
+            baud: None,
+            parity: None,
+            stopbits: None,
+            flow: None,
+            databits: None,
+            frame: None,
+            codec: None,
+            label: None,
+        };
+        for (key, value) in pairs {
+            let select = match key {
+                "name" => Some(Selector::Name(value.to_string())),
+                "serial" => Some(Selector::Serial(value.to_string())),
+                "index" => Some(Selector::Index(parse(key, value)?)),
+                _ => None,
+            };
+            if let Some(select) = select {
+                if selector.replace(select).is_some() {
+                    return Err("only one of name, serial or index may be given".to_string());
+                }
+                continue;
+            }
+            match key {
+                "baud" => settings.baud = Some(parse(key, value)?),
+                "parity" => settings.parity = Some(parse(key, value)?),
+                "stopbits" => settings.stopbits = Some(parse(key, value)?),
+                "flow" => settings.flow = Some(parse(key, value)?),
+                "databits" => settings.databits = Some(parse(key, value)?),
+                "frame" => settings.frame = Some(parse(key, value)?),
+                "codec" => settings.codec = Some(parse(key, value)?),
+                "label" => settings.label = Some(value.to_string()),
+                _ => return Err(format!("unknown port setting '{}'", key)),
+            }
+        }
+        settings.selector =
+            selector.ok_or_else(|| "one of name, serial or index is required".to_string())?;
+        Ok(settings)
+    }
+
+    // Returns true if these settings are for the index'th port found.
+    pub fn matches(&self, index: usize, info: &SerialPortInfo) -> bool {
+        match &self.selector {
+            Selector::Name(name) => {
+                info.port_name == *name
+                    || Path::new(&info.port_name).file_name() == Some(name.as_ref())
+            }
+            Selector::Serial(serial) => match &info.port_type {
+                tokio_serial::SerialPortType::UsbPort(usb) => {
+                    usb.serial_number.as_ref() == Some(serial)
+                }
+                _ => false,
+            },
+            Selector::Index(wanted) => index == *wanted,
+        }
+    }
+
+    // Replaces the global options with the ones given for this port.
+    pub fn apply(&self, opt: &mut Opt) {
+        opt.baud = self.baud.unwrap_or(opt.baud);
+        opt.parity = self.parity.unwrap_or(opt.parity);
+        opt.stopbits = self.stopbits.unwrap_or(opt.stopbits);
+        opt.flow = self.flow.unwrap_or(opt.flow);
+        opt.databits = self.databits.unwrap_or(opt.databits);
+        opt.frame = self.frame.unwrap_or(opt.frame);
+        opt.codec = self.codec.unwrap_or(opt.codec);
+        if self.label.is_some() {
+            opt.label = self.label.clone();
+        }
+    }
+}
+
+impl FromStr for PortSettings {
+    type Err = String;
+
+    // Parses "name=/dev/ttyUSB1,baud=115200,codec=char,label=console".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pairs = s
+            .split(',')
+            .map(|pair| {
+                pair.split_once('=')
+                    .ok_or_else(|| format!("expected key=value, found '{}'", pair))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_pairs(pairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_serial::SerialPortType;
+
+    #[test]
+    fn from_str_test() {
+        let settings: PortSettings = "name=ttyUSB1,baud=115200,frame=delim:0x03,label=console"
+            .parse()
+            .unwrap();
+        assert_eq!(settings.selector, Selector::Name("ttyUSB1".to_string()));
+        assert_eq!(settings.baud, Some(115200));
+        assert_eq!(settings.frame, Some(FrameOpt::Delim(0x03)));
+        assert_eq!(settings.label.as_deref(), Some("console"));
+
+        assert!("baud=115200".parse::<PortSettings>().is_err());
+        assert!("index=0,serial=A1".parse::<PortSettings>().is_err());
+        assert!("index=0,speed=9600".parse::<PortSettings>().is_err());
+    }
+
+    #[test]
+    fn matches_test() {
+        let info = SerialPortInfo {
+            port_name: "/dev/ttyUSB1".to_string(),
+            port_type: SerialPortType::Unknown,
+        };
+        let settings: PortSettings = "name=ttyUSB1".parse().unwrap();
+        assert!(settings.matches(3, &info));
+        let settings: PortSettings = "index=3".parse().unwrap();
+        assert!(settings.matches(3, &info));
+        let settings: PortSettings = "serial=A1".parse().unwrap();
+        assert!(!settings.matches(3, &info));
+    }
+}
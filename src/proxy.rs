@@ -0,0 +1,142 @@
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time;
+
+use crate::codec::Codec;
+use crate::error::ProgramError;
+use crate::fault::{self, Injector};
+use crate::framing::FrameOpt;
+use crate::interface::serial;
+use crate::opt::{FaultSideOpt, Opt, ProxyOpt};
+
+// Forwards traffic between the port at `path` and the peer port, frame by
+// frame, displaying each frame with the name of the port that sent it and
+// injecting the faults given with --fault. Returns the code to exit with if
+// a trigger match stopped it.
+pub async fn run(path: &str, opt: &Opt, proxy: &ProxyOpt) -> Result<Option<i32>, ProgramError> {
+    let mut peer_opt = opt.clone();
+    peer_opt.baud = proxy.peer_baud.unwrap_or(opt.baud);
+    // The label names the port, not the peer.
+    peer_opt.label = None;
+
+    let port = serial::open(path, opt)?;
+    let peer = serial::open(&proxy.peer, &peer_opt)?;
+
+    let seed = proxy.seed.unwrap_or_else(fault::random_seed);
+    let mut events = Codec::new(path.to_string(), opt);
+    events.event(&format!("proxying to {}", proxy.peer));
+    if !proxy.faults.is_empty() {
+        events.event(&format!("fault seed {}", seed));
+    }
+    let injector = |side: FaultSideOpt, seed: u64| {
+        (proxy.fault_from == FaultSideOpt::Both || proxy.fault_from == side)
+            .then(|| Injector::new(&proxy.faults, seed))
+    };
+
+    let (port_rx, port_tx) = tokio::io::split(port);
+    let (peer_rx, peer_tx) = tokio::io::split(peer);
+    let code = tokio::select! {
+        code = forward(
+            port_rx,
+            peer_tx,
+            Codec::new(path.to_string(), opt),
+            opt.frame,
+            injector(FaultSideOpt::Port, seed),
+        ) => code,
+        code = forward(
+            peer_rx,
+            port_tx,
+            Codec::new(proxy.peer.clone(), &peer_opt),
+            opt.frame,
+            injector(FaultSideOpt::Peer, seed.wrapping_add(1)),
+        ) => code,
+    };
+    Ok(code)
+}
+
+// Copies the frames read from `from` to `to`. Returns once `from` is closed
+// or either side fails, or once a trigger match stopped it, with the code
+// to exit with then.
+async fn forward<R, W>(
+    mut from: R,
+    mut to: W,
+    mut codec: Codec,
+    frame: FrameOpt,
+    mut injector: Option<Injector>,
+) -> Option<i32>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut pending = BytesMut::new();
+    let mut buf = [0u8; 1024];
+    while codec.exit_code().is_none() {
+        let read = match frame.idle() {
+            Some(idle) if !pending.is_empty() => {
+                match time::timeout(idle, from.read(&mut buf)).await {
+                    Ok(read) => read,
+                    Err(_) => {
+                        let frame = pending.split();
+                        if !send(&mut to, &mut codec, frame, &mut injector).await {
+                            break;
+                        }
+                        continue;
+                    }
+                }
+            }
+            _ => from.read(&mut buf).await,
+        };
+        match read {
+            Ok(0) => break,
+            Ok(count) => pending.extend_from_slice(&buf[..count]),
+            Err(err) => {
+                codec.error(&err);
+                break;
+            }
+        }
+        while let Some(frame) = frame.split(&mut pending) {
+            if !send(&mut to, &mut codec, frame, &mut injector).await || codec.exit_code().is_some()
+            {
+                return codec.exit_code();
+            }
+        }
+    }
+    if !pending.is_empty() && codec.exit_code().is_none() {
+        send(&mut to, &mut codec, pending, &mut injector).await;
+    }
+    codec.exit_code()
+}
+
+// Applies the faults to a frame, then displays and writes what is left of
+// it. Returns false if writing failed.
+async fn send<W>(
+    to: &mut W,
+    codec: &mut Codec,
+    mut frame: BytesMut,
+    injector: &mut Option<Injector>,
+) -> bool
+where
+    W: AsyncWrite + Unpin,
+{
+    let outcome = match injector {
+        Some(injector) => injector.apply(&mut frame),
+        None => fault::Outcome {
+            copies: 1,
+            ..Default::default()
+        },
+    };
+    codec.display(&frame);
+    for fault in &outcome.faults {
+        codec.event(&format!("fault: {}", fault));
+    }
+    if !outcome.delay.is_zero() {
+        time::sleep(outcome.delay).await;
+    }
+    for _ in 0..outcome.copies {
+        if let Err(err) = to.write_all(&frame).await {
+            codec.error(&err);
+            return false;
+        }
+    }
+    true
+}
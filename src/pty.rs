@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+use tokio_serial::{SerialPort, SerialStream};
+
+use crate::codec::Codec;
+use crate::error::ProgramError;
+use crate::interface::serial;
+use crate::opt::{Opt, PtyOpt};
+use crate::relay;
+
+// Points `link` at the pseudo terminal, replacing a link left behind by a
+// previous run.
+fn create_link(name: &str, link: &Path) -> Result<(), ProgramError> {
+    if fs::symlink_metadata(link).is_ok_and(|x| x.file_type().is_symlink()) {
+        fs::remove_file(link).map_err(ProgramError::IoError)?;
+    }
+    std::os::unix::fs::symlink(name, link).map_err(ProgramError::IoError)
+}
+
+// Creates a pseudo terminal for an application to open instead of the
+// port. What the application writes is relayed to the port at `path` and
+// what the port receives is relayed back, both directions being displayed.
+// Without a port, what the application writes is echoed back to it.
+// Returns the code to exit with if a trigger match stopped the relay.
+pub async fn run(path: Option<&str>, opt: &Opt, pty: &PtyOpt) -> Result<Option<i32>, ProgramError> {
+    // The application side stays open here too, so that the pseudo
+    // terminal survives the application closing and reopening it.
+    let (master, slave) = SerialStream::pair().map_err(ProgramError::SerialPortError)?;
+    let name = slave.name().unwrap_or_default();
+
+    let mut events = Codec::new(name.clone(), opt);
+    events.event(&format!("application side is {}", name));
+    if let Some(link) = &pty.link {
+        create_link(&name, link)?;
+        events.event(&format!("linked from {}", link.display()));
+    }
+
+    let relay = async {
+        match path {
+            Some(path) => {
+                let port = serial::open(path, opt)?;
+                Ok(relay::relay(
+                    master,
+                    Codec::new(path.to_string(), opt).with_direction("tx"),
+                    port,
+                    Codec::new(path.to_string(), opt),
+                )
+                .await)
+            }
+            None => {
+                let (rx, tx) = tokio::io::split(master);
+                Ok(relay::copy(rx, tx, Codec::new(name.clone(), opt)).await)
+            }
+        }
+    };
+    let result = tokio::select! {
+        result = relay => result,
+        _ = tokio::signal::ctrl_c() => Ok(None),
+    };
+
+    if let Some(link) = &pty.link {
+        let _ = fs::remove_file(link);
+    }
+    drop(slave);
+    result
+}
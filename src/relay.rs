@@ -0,0 +1,94 @@
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time;
+use tokio_util::codec::Decoder;
+
+use crate::codec::Codec;
+
+// Copies everything read from `from` to `to` and displays it with `codec`.
+// Returns once `from` is closed or either side fails, or once a trigger
+// match stopped it, with the code to exit with then.
+pub async fn copy<R, W>(mut from: R, mut to: W, mut codec: Codec) -> Option<i32>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut display = BytesMut::new();
+    let mut buf = [0u8; 1024];
+    while codec.exit_code().is_none() {
+        let read = match codec.idle() {
+            Some(idle) => match time::timeout(idle, from.read(&mut buf)).await {
+                Ok(read) => read,
+                Err(_) => {
+                    codec.flush(&mut display);
+                    continue;
+                }
+            },
+            None => from.read(&mut buf).await,
+        };
+        match read {
+            Ok(0) => break,
+            Ok(count) => {
+                if let Err(err) = to.write_all(&buf[..count]).await {
+                    codec.error(&err);
+                    break;
+                }
+                display.extend_from_slice(&buf[..count]);
+                let _ = codec.decode(&mut display);
+            }
+            Err(err) => {
+                codec.error(&err);
+                break;
+            }
+        }
+    }
+    let _ = codec.decode_eof(&mut display);
+    codec.exit_code()
+}
+
+// Relays two streams to each other until either side is closed. What `a`
+// sends is displayed with `a_codec`, what `b` sends with `b_codec`. Returns
+// the code to exit with if a trigger match stopped it.
+pub async fn relay<A, B>(a: A, a_codec: Codec, b: B, b_codec: Codec) -> Option<i32>
+where
+    A: AsyncRead + AsyncWrite,
+    B: AsyncRead + AsyncWrite,
+{
+    let (a_rx, a_tx) = tokio::io::split(a);
+    let (b_rx, b_tx) = tokio::io::split(b);
+    tokio::select! {
+        code = copy(a_rx, b_tx, a_codec) => code,
+        code = copy(b_rx, a_tx, b_codec) => code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::Opt;
+    use structopt::StructOpt;
+
+    #[tokio::test]
+    async fn copy_test() {
+        let opt = Opt::from_iter(["serial-monitor"]);
+        let (mut app, from) = tokio::io::duplex(64);
+        let (to, mut port) = tokio::io::duplex(64);
+        app.write_all(b"\x02ABC\x03").await.unwrap();
+        drop(app);
+        copy(from, to, Codec::new("test".to_string(), &opt)).await;
+        let mut copied = Vec::new();
+        port.read_to_end(&mut copied).await.unwrap();
+        assert_eq!(copied, b"\x02ABC\x03");
+    }
+
+    #[tokio::test]
+    async fn copy_trigger_exit_test() {
+        let opt = Opt::from_iter(["serial-monitor", "--trigger", "41", "--trigger-exit", "3"]);
+        let (mut app, from) = tokio::io::duplex(64);
+        let (to, _port) = tokio::io::duplex(64);
+        app.write_all(b"\x02ABC\x03").await.unwrap();
+        // Returns while the application side is still open.
+        let code = copy(from, to, Codec::new("test".to_string(), &opt)).await;
+        assert_eq!(code, Some(3));
+    }
+}
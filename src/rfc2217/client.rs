@@ -0,0 +1,245 @@
+use bytes::BytesMut;
+use std::future;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{self, Instant};
+use tokio_util::codec::Decoder;
+
+use super::com_port::{self, *};
+use super::telnet::{self, Event, Negotiation, Parser, BINARY, COM_PORT_OPTION, SGA};
+use crate::codec::Codec;
+use crate::control::Control;
+use crate::error::ProgramError;
+use crate::modem::ModemLines;
+use crate::opt::{DataBitsOpt, Opt, StopBitsOpt};
+
+// Length of a BREAK sent from the keyboard when --break-ms is not given.
+const DEFAULT_BREAK_MS: u64 = 250;
+
+// Displays a port served over RFC 2217, like Monitor does for a local port.
+pub struct Client {
+    stream: TcpStream,
+    codec: Codec,
+    parser: Parser,
+    negotiation: Negotiation,
+    pending: BytesMut,
+    controls: Option<broadcast::Receiver<Control>>,
+    // Commands sent once the connection is up.
+    settings: Vec<Command>,
+    // None until the server reports the modem lines, or when they are not
+    // monitored.
+    modem_lines: Option<ModemLines>,
+    show_modem_lines: bool,
+    dtr: bool,
+    rts: bool,
+    break_ms: Option<u64>,
+}
+
+impl Client {
+    // Connects to the server at `url` (rfc2217://host:port).
+    pub async fn connect(
+        url: &str,
+        opt: &Opt,
+        controls: Option<broadcast::Receiver<Control>>,
+    ) -> Result<Self, ProgramError> {
+        let data_bits = DataBitsOpt::try_from(opt.databits).map_err(ProgramError::IoError)?;
+        let stop_bits = StopBitsOpt::try_from(opt.stopbits).map_err(ProgramError::IoError)?;
+        let address = super::address(url).unwrap_or(url);
+        let stream = TcpStream::connect(address)
+            .await
+            .map_err(|x| ProgramError::UnableToOpen(url.to_string(), x.into()))?;
+
+        let mut settings = vec![
+            Command::Signature(Vec::new()),
+            Command::SetBaudRate(opt.baud),
+            Command::SetDataSize(com_port::data_size(data_bits.0)),
+            Command::SetParity(com_port::parity(opt.parity.into())),
+            Command::SetStopSize(com_port::stop_size(stop_bits.0)),
+            Command::SetControl(com_port::flow_control(opt.flow.into())),
+        ];
+        if let Some(level) = opt.dtr {
+            settings.push(Command::SetControl(if level.into() {
+                DTR_ON
+            } else {
+                DTR_OFF
+            }));
+        }
+        if let Some(level) = opt.rts {
+            settings.push(Command::SetControl(if level.into() {
+                RTS_ON
+            } else {
+                RTS_OFF
+            }));
+        }
+        if opt.modem_lines {
+            settings.push(Command::SetModemStateMask(0xff));
+        }
+
+        Ok(Self {
+            stream,
+            codec: Codec::new(url.to_string(), opt).with_link(opt.link_settings()),
+            parser: Parser::default(),
+            negotiation: Negotiation::new(&[BINARY, SGA, COM_PORT_OPTION], &[BINARY, SGA]),
+            pending: BytesMut::new(),
+            controls,
+            settings,
+            modem_lines: None,
+            show_modem_lines: opt.modem_lines,
+            dtr: true,
+            rts: true,
+            break_ms: opt.break_ms,
+        })
+    }
+
+    // Runs until the server closes the connection, or until a trigger match
+    // stops it. Returns the code to exit with in that case.
+    pub async fn run(mut self) -> Option<i32> {
+        let mut start = self.negotiation.start();
+        for command in &self.settings {
+            start.extend(command.encode(false));
+        }
+        self.send(&start).await;
+        if let Some(ms) = self.break_ms {
+            self.send_break(ms).await;
+        }
+
+        let mut buf = [0u8; 1024];
+        while self.codec.exit_code().is_none() {
+            let link_deadline = self.codec.link_deadline().map(Into::into);
+            let idle_deadline = self.codec.idle_deadline();
+            tokio::select! {
+                read = self.stream.read(&mut buf) => match read {
+                    Ok(0) => break,
+                    Ok(count) => {
+                        let events = self.parser.feed(&buf[..count]);
+                        for event in events {
+                            self.event(event).await;
+                        }
+                        self.write_link().await;
+                    }
+                    Err(err) => {
+                        self.codec.error(&err);
+                        break;
+                    }
+                },
+                _ = Self::sleep_until(link_deadline) => {
+                    self.codec.poll_link();
+                    self.write_link().await;
+                }
+                _ = Self::sleep_until(idle_deadline) => self.codec.flush(&mut self.pending),
+                control = Self::control(&mut self.controls) => match control {
+                    Ok(Control::ToggleDtr) => {
+                        let control = if self.dtr { DTR_OFF } else { DTR_ON };
+                        self.send(&Command::SetControl(control).encode(false)).await;
+                    }
+                    Ok(Control::ToggleRts) => {
+                        let control = if self.rts { RTS_OFF } else { RTS_ON };
+                        self.send(&Command::SetControl(control).encode(false)).await;
+                    }
+                    Ok(Control::Break) => {
+                        self.send_break(self.break_ms.unwrap_or(DEFAULT_BREAK_MS)).await
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => self.controls = None,
+                },
+            }
+        }
+        self.codec.flush(&mut self.pending);
+        self.codec.exit_code()
+    }
+
+    async fn sleep_until(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => time::sleep_until(deadline).await,
+            None => future::pending().await,
+        }
+    }
+
+    // Sends the answers of the link layer to the port through the server.
+    async fn write_link(&mut self) {
+        for bytes in self.codec.take_outgoing() {
+            self.send(&telnet::escape(&bytes)).await;
+        }
+    }
+
+    async fn control(
+        controls: &mut Option<broadcast::Receiver<Control>>,
+    ) -> Result<Control, RecvError> {
+        match controls {
+            Some(controls) => controls.recv().await,
+            None => future::pending().await,
+        }
+    }
+
+    async fn event(&mut self, event: Event) {
+        match event {
+            Event::Data(data) => {
+                self.pending.extend_from_slice(&data);
+                let _ = self.codec.decode(&mut self.pending);
+            }
+            Event::Negotiate(verb, option) => {
+                let answer = self.negotiation.answer(verb, option);
+                self.send(&answer).await;
+            }
+            Event::Subnegotiation(COM_PORT_OPTION, payload) => {
+                if let Some(command) = Command::decode(&payload) {
+                    self.reply(command);
+                }
+            }
+            Event::Subnegotiation(..) => {}
+        }
+    }
+
+    // Displays what the server reports.
+    fn reply(&mut self, command: Command) {
+        match command {
+            Command::NotifyModemState(state) => {
+                if !self.show_modem_lines {
+                    return;
+                }
+                let lines = ModemLines::from_state(state);
+                match self.modem_lines.replace(lines) {
+                    None => self.codec.event(&format!("modem {}", lines)),
+                    Some(previous) => {
+                        for change in lines.changes(&previous) {
+                            self.codec.event(&format!("modem {}", change));
+                        }
+                    }
+                }
+            }
+            // Line state notifications are only asked for by other clients.
+            Command::NotifyLineState(_) => {}
+            command => {
+                match command {
+                    Command::SetControl(DTR_ON | DTR_OFF) => {
+                        self.dtr = command == Command::SetControl(DTR_ON)
+                    }
+                    Command::SetControl(RTS_ON | RTS_OFF) => {
+                        self.rts = command == Command::SetControl(RTS_ON)
+                    }
+                    _ => {}
+                }
+                self.codec.event(&command.to_string());
+            }
+        }
+    }
+
+    async fn send(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        if let Err(err) = self.stream.write_all(bytes).await {
+            self.codec.error(&err);
+        }
+    }
+
+    async fn send_break(&mut self, ms: u64) {
+        self.send(&Command::SetControl(BREAK_ON).encode(false))
+            .await;
+        time::sleep(Duration::from_millis(ms)).await;
+        self.send(&Command::SetControl(BREAK_OFF).encode(false))
+            .await;
+    }
+}
@@ -0,0 +1,263 @@
+use std::fmt;
+use tokio_serial::{DataBits, FlowControl, Parity, StopBits};
+
+use super::telnet::{self, COM_PORT_OPTION};
+
+const SIGNATURE: u8 = 0;
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const NOTIFY_LINESTATE: u8 = 6;
+const NOTIFY_MODEMSTATE: u8 = 7;
+const FLOWCONTROL_SUSPEND: u8 = 8;
+const FLOWCONTROL_RESUME: u8 = 9;
+const SET_LINESTATE_MASK: u8 = 10;
+const SET_MODEMSTATE_MASK: u8 = 11;
+const PURGE_DATA: u8 = 12;
+// Added to the command codes sent by the server.
+const SERVER_OFFSET: u8 = 100;
+
+// SET-CONTROL values.
+pub const FLOW_QUERY: u8 = 0;
+pub const FLOW_NONE: u8 = 1;
+pub const FLOW_SOFTWARE: u8 = 2;
+pub const FLOW_HARDWARE: u8 = 3;
+pub const BREAK_QUERY: u8 = 4;
+pub const BREAK_ON: u8 = 5;
+pub const BREAK_OFF: u8 = 6;
+pub const DTR_QUERY: u8 = 7;
+pub const DTR_ON: u8 = 8;
+pub const DTR_OFF: u8 = 9;
+pub const RTS_QUERY: u8 = 10;
+pub const RTS_ON: u8 = 11;
+pub const RTS_OFF: u8 = 12;
+
+// PURGE-DATA values.
+pub const PURGE_RX: u8 = 1;
+pub const PURGE_TX: u8 = 2;
+pub const PURGE_BOTH: u8 = 3;
+
+// A COM-PORT-OPTION subnegotiation. A value of 0 asks for the current
+// setting instead of changing it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Signature(Vec<u8>),
+    SetBaudRate(u32),
+    SetDataSize(u8),
+    SetParity(u8),
+    SetStopSize(u8),
+    SetControl(u8),
+    NotifyLineState(u8),
+    NotifyModemState(u8),
+    FlowControlSuspend,
+    FlowControlResume,
+    SetLineStateMask(u8),
+    SetModemStateMask(u8),
+    PurgeData(u8),
+}
+
+impl Command {
+    // Decodes the payload of a COM-PORT-OPTION subnegotiation sent by
+    // either side.
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let (code, value) = payload.split_first()?;
+        let code = code.checked_sub(SERVER_OFFSET).unwrap_or(*code);
+        let byte = value.first().copied();
+        Some(match code {
+            SIGNATURE => Self::Signature(value.to_vec()),
+            SET_BAUDRATE => Self::SetBaudRate(u32::from_be_bytes(value.try_into().ok()?)),
+            SET_DATASIZE => Self::SetDataSize(byte?),
+            SET_PARITY => Self::SetParity(byte?),
+            SET_STOPSIZE => Self::SetStopSize(byte?),
+            SET_CONTROL => Self::SetControl(byte?),
+            NOTIFY_LINESTATE => Self::NotifyLineState(byte?),
+            NOTIFY_MODEMSTATE => Self::NotifyModemState(byte?),
+            FLOWCONTROL_SUSPEND => Self::FlowControlSuspend,
+            FLOWCONTROL_RESUME => Self::FlowControlResume,
+            SET_LINESTATE_MASK => Self::SetLineStateMask(byte?),
+            SET_MODEMSTATE_MASK => Self::SetModemStateMask(byte?),
+            PURGE_DATA => Self::PurgeData(byte?),
+            _ => return None,
+        })
+    }
+
+    // Encodes the whole subnegotiation, as sent by the server or the client.
+    pub fn encode(&self, server: bool) -> Vec<u8> {
+        let (code, value) = match self {
+            Self::Signature(signature) => (SIGNATURE, signature.clone()),
+            Self::SetBaudRate(baud) => (SET_BAUDRATE, baud.to_be_bytes().to_vec()),
+            Self::SetDataSize(x) => (SET_DATASIZE, vec![*x]),
+            Self::SetParity(x) => (SET_PARITY, vec![*x]),
+            Self::SetStopSize(x) => (SET_STOPSIZE, vec![*x]),
+            Self::SetControl(x) => (SET_CONTROL, vec![*x]),
+            Self::NotifyLineState(x) => (NOTIFY_LINESTATE, vec![*x]),
+            Self::NotifyModemState(x) => (NOTIFY_MODEMSTATE, vec![*x]),
+            Self::FlowControlSuspend => (FLOWCONTROL_SUSPEND, Vec::new()),
+            Self::FlowControlResume => (FLOWCONTROL_RESUME, Vec::new()),
+            Self::SetLineStateMask(x) => (SET_LINESTATE_MASK, vec![*x]),
+            Self::SetModemStateMask(x) => (SET_MODEMSTATE_MASK, vec![*x]),
+            Self::PurgeData(x) => (PURGE_DATA, vec![*x]),
+        };
+        let mut payload = vec![if server { code + SERVER_OFFSET } else { code }];
+        payload.extend(value);
+        telnet::subnegotiation(COM_PORT_OPTION, &payload)
+    }
+
+    // Returns the command asking for the setting this one changes, used to
+    // answer clients that are not allowed to change it.
+    pub fn query(&self) -> Self {
+        match self {
+            Self::SetBaudRate(_) => Self::SetBaudRate(0),
+            Self::SetDataSize(_) => Self::SetDataSize(0),
+            Self::SetParity(_) => Self::SetParity(0),
+            Self::SetStopSize(_) => Self::SetStopSize(0),
+            Self::SetControl(x) => Self::SetControl(match *x {
+                FLOW_QUERY..=FLOW_HARDWARE => FLOW_QUERY,
+                BREAK_QUERY..=BREAK_OFF => BREAK_QUERY,
+                DTR_QUERY..=DTR_OFF => DTR_QUERY,
+                RTS_QUERY..=RTS_OFF => RTS_QUERY,
+                x => x,
+            }),
+            command => command.clone(),
+        }
+    }
+}
+
+pub fn data_size(bits: DataBits) -> u8 {
+    match bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    }
+}
+
+pub fn parity(parity: Parity) -> u8 {
+    match parity {
+        Parity::None => 1,
+        Parity::Odd => 2,
+        Parity::Even => 3,
+    }
+}
+
+pub fn stop_size(bits: StopBits) -> u8 {
+    match bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    }
+}
+
+pub fn flow_control(flow: FlowControl) -> u8 {
+    match flow {
+        FlowControl::None => FLOW_NONE,
+        FlowControl::Software => FLOW_SOFTWARE,
+        FlowControl::Hardware => FLOW_HARDWARE,
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Signature(signature) => {
+                write!(f, "signature '{}'", String::from_utf8_lossy(signature))
+            }
+            Self::SetBaudRate(baud) => write!(f, "baud {}", baud),
+            Self::SetDataSize(bits) => write!(f, "databits {}", bits),
+            Self::SetParity(x) => match x {
+                1 => write!(f, "parity none"),
+                2 => write!(f, "parity odd"),
+                3 => write!(f, "parity even"),
+                4 => write!(f, "parity mark"),
+                5 => write!(f, "parity space"),
+                x => write!(f, "parity {}", x),
+            },
+            Self::SetStopSize(x) => match x {
+                3 => write!(f, "stopbits 1.5"),
+                x => write!(f, "stopbits {}", x),
+            },
+            Self::SetControl(x) => match *x {
+                FLOW_NONE => write!(f, "flow none"),
+                FLOW_SOFTWARE => write!(f, "flow software"),
+                FLOW_HARDWARE => write!(f, "flow hardware"),
+                BREAK_ON | BREAK_OFF => write!(f, "BREAK {}", on_off(*x == BREAK_ON)),
+                DTR_ON | DTR_OFF => write!(f, "DTR {}", on_off(*x == DTR_ON)),
+                RTS_ON | RTS_OFF => write!(f, "RTS {}", on_off(*x == RTS_ON)),
+                x => write!(f, "control {}", x),
+            },
+            Self::NotifyLineState(x) => write!(f, "line state 0x{:02x}", x),
+            Self::NotifyModemState(x) => write!(f, "modem state 0x{:02x}", x),
+            Self::FlowControlSuspend => write!(f, "flow control suspend"),
+            Self::FlowControlResume => write!(f, "flow control resume"),
+            Self::SetLineStateMask(x) => write!(f, "line state mask 0x{:02x}", x),
+            Self::SetModemStateMask(x) => write!(f, "modem state mask 0x{:02x}", x),
+            Self::PurgeData(x) => match *x {
+                PURGE_RX => write!(f, "purge rx"),
+                PURGE_TX => write!(f, "purge tx"),
+                PURGE_BOTH => write!(f, "purge rx and tx"),
+                x => write!(f, "purge {}", x),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rfc2217::telnet::{Event, Parser};
+
+    #[test]
+    fn round_trip_test() {
+        let command = Command::SetBaudRate(0x0001_ff00);
+        let encoded = command.encode(true);
+        // The 0xff byte of the baud rate is escaped.
+        assert_eq!(
+            encoded,
+            vec![255, 250, 44, 101, 0x00, 0x01, 0xff, 0xff, 0x00, 255, 240]
+        );
+        let events = Parser::default().feed(&encoded);
+        assert_eq!(
+            events,
+            vec![Event::Subnegotiation(44, vec![101, 0x00, 0x01, 0xff, 0x00])]
+        );
+        assert_eq!(
+            Command::decode(&[101, 0x00, 0x01, 0xff, 0x00]),
+            Some(command)
+        );
+        assert_eq!(
+            Command::decode(&[5, DTR_ON]),
+            Some(Command::SetControl(DTR_ON))
+        );
+        assert_eq!(Command::decode(&[1, 0]), None);
+    }
+
+    #[test]
+    fn query_test() {
+        assert_eq!(Command::SetBaudRate(9600).query(), Command::SetBaudRate(0));
+        assert_eq!(
+            Command::SetControl(FLOW_HARDWARE).query(),
+            Command::SetControl(FLOW_QUERY)
+        );
+        assert_eq!(
+            Command::SetControl(BREAK_OFF).query(),
+            Command::SetControl(BREAK_QUERY)
+        );
+        assert_eq!(
+            Command::SetControl(DTR_ON).query(),
+            Command::SetControl(DTR_QUERY)
+        );
+        assert_eq!(
+            Command::SetControl(RTS_OFF).query(),
+            Command::SetControl(RTS_QUERY)
+        );
+    }
+}
@@ -0,0 +1,19 @@
+// RFC 2217 carries a serial port over Telnet, along with its settings and
+// control lines, so that a remote port can be used as if it were local.
+
+mod client;
+mod com_port;
+mod server;
+mod telnet;
+
+pub use client::Client;
+pub use com_port::Command;
+pub use server::{Lines, Request, Session};
+pub use telnet::escape;
+
+const SCHEME: &str = "rfc2217://";
+
+// Returns host:port if the path is an rfc2217:// URL.
+pub fn address(path: &str) -> Option<&str> {
+    path.strip_prefix(SCHEME)
+}
@@ -0,0 +1,279 @@
+use tokio_serial::{
+    ClearBuffer, DataBits, FlowControl, Parity, SerialPort, SerialStream, StopBits,
+};
+
+use super::com_port::{self, *};
+use super::telnet::{Event, Negotiation, Parser, BINARY, COM_PORT_OPTION, SGA};
+use crate::modem::ModemLines;
+
+const SIGNATURE: &str = concat!("serial_monitor ", env!("CARGO_PKG_VERSION"));
+
+// What a client asked for, once the Telnet commands are taken out.
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    // Data to write to the port.
+    Data(Vec<u8>),
+    // A command to apply to the port, whose reply goes back to the client.
+    Command(Command),
+    // Bytes to send back to the client right away.
+    Reply(Vec<u8>),
+}
+
+// The Telnet side of one RFC 2217 client.
+pub struct Session {
+    parser: Parser,
+    negotiation: Negotiation,
+    modem_mask: u8,
+    modem_lines: ModemLines,
+    // The client asked us to stop sending data for a while.
+    pub suspended: bool,
+}
+
+impl Session {
+    pub fn new(modem_lines: ModemLines) -> Self {
+        Self {
+            parser: Parser::default(),
+            negotiation: Negotiation::new(&[BINARY, SGA], &[BINARY, SGA, COM_PORT_OPTION]),
+            modem_mask: 0,
+            modem_lines,
+            suspended: false,
+        }
+    }
+
+    // Returns the negotiation to send when the client connects.
+    pub fn start(&mut self) -> Vec<u8> {
+        self.negotiation.start()
+    }
+
+    pub fn receive(&mut self, input: &[u8]) -> Vec<Request> {
+        let mut requests = Vec::new();
+        for event in self.parser.feed(input) {
+            match event {
+                Event::Data(data) => requests.push(Request::Data(data)),
+                Event::Negotiate(verb, option) => {
+                    let answer = self.negotiation.answer(verb, option);
+                    if !answer.is_empty() {
+                        requests.push(Request::Reply(answer));
+                    }
+                }
+                Event::Subnegotiation(COM_PORT_OPTION, payload) => {
+                    if let Some(command) = Command::decode(&payload) {
+                        requests.extend(self.command(command));
+                    }
+                }
+                Event::Subnegotiation(..) => {}
+            }
+        }
+        requests
+    }
+
+    // Handles the commands that only concern this client.
+    fn command(&mut self, command: Command) -> Vec<Request> {
+        match command {
+            Command::Signature(signature) if signature.is_empty() => {
+                let signature = Command::Signature(SIGNATURE.as_bytes().to_vec());
+                vec![Request::Reply(signature.encode(true))]
+            }
+            // The client told us who it is.
+            Command::Signature(_) => Vec::new(),
+            Command::SetModemStateMask(mask) => {
+                self.modem_mask = mask;
+                let state = self.modem_lines.state(&self.modem_lines) & mask;
+                vec![
+                    Request::Reply(command.encode(true)),
+                    Request::Reply(Command::NotifyModemState(state).encode(true)),
+                ]
+            }
+            Command::FlowControlSuspend => {
+                self.suspended = true;
+                Vec::new()
+            }
+            Command::FlowControlResume => {
+                self.suspended = false;
+                Vec::new()
+            }
+            command => vec![Request::Command(command)],
+        }
+    }
+
+    // Returns the notification to send when the modem lines change, if the
+    // client asked for it.
+    pub fn modem_state(&mut self, lines: ModemLines) -> Option<Vec<u8>> {
+        let state = lines.state(&self.modem_lines) & self.modem_mask;
+        self.modem_lines = lines;
+        (state & 0x0F != 0).then(|| Command::NotifyModemState(state).encode(true))
+    }
+}
+
+// Control line levels, which cannot be read back from the port.
+pub struct Lines {
+    dtr: bool,
+    rts: bool,
+    brk: bool,
+}
+
+impl Default for Lines {
+    // Most ports assert DTR and RTS when they are opened.
+    fn default() -> Self {
+        Self {
+            dtr: true,
+            rts: true,
+            brk: false,
+        }
+    }
+}
+
+impl Lines {
+    // Applies a command from a client to the port and returns the reply,
+    // which holds the setting now in use.
+    pub fn apply(
+        &mut self,
+        port: &mut SerialStream,
+        command: &Command,
+    ) -> tokio_serial::Result<Option<Command>> {
+        let reply = match *command {
+            Command::SetBaudRate(baud) => {
+                if baud != 0 {
+                    port.set_baud_rate(baud)?;
+                }
+                Command::SetBaudRate(port.baud_rate()?)
+            }
+            Command::SetDataSize(bits) => {
+                let bits = match bits {
+                    5 => Some(DataBits::Five),
+                    6 => Some(DataBits::Six),
+                    7 => Some(DataBits::Seven),
+                    8 => Some(DataBits::Eight),
+                    _ => None,
+                };
+                if let Some(bits) = bits {
+                    port.set_data_bits(bits)?;
+                }
+                Command::SetDataSize(com_port::data_size(port.data_bits()?))
+            }
+            Command::SetParity(parity) => {
+                // Mark and space parity are not supported.
+                let parity = match parity {
+                    1 => Some(Parity::None),
+                    2 => Some(Parity::Odd),
+                    3 => Some(Parity::Even),
+                    _ => None,
+                };
+                if let Some(parity) = parity {
+                    port.set_parity(parity)?;
+                }
+                Command::SetParity(com_port::parity(port.parity()?))
+            }
+            Command::SetStopSize(bits) => {
+                // 1.5 stop bits are not supported.
+                let bits = match bits {
+                    1 => Some(StopBits::One),
+                    2 => Some(StopBits::Two),
+                    _ => None,
+                };
+                if let Some(bits) = bits {
+                    port.set_stop_bits(bits)?;
+                }
+                Command::SetStopSize(com_port::stop_size(port.stop_bits()?))
+            }
+            Command::SetControl(control) => Command::SetControl(self.control(port, control)?),
+            Command::PurgeData(purge) => {
+                match purge {
+                    PURGE_RX => port.clear(ClearBuffer::Input)?,
+                    PURGE_TX => port.clear(ClearBuffer::Output)?,
+                    PURGE_BOTH => port.clear(ClearBuffer::All)?,
+                    _ => {}
+                }
+                Command::PurgeData(purge)
+            }
+            // Line state notifications are not supported, but the mask is
+            // acknowledged as the RFC requires.
+            Command::SetLineStateMask(mask) => Command::SetLineStateMask(mask),
+            _ => return Ok(None),
+        };
+        Ok(Some(reply))
+    }
+
+    fn control(&mut self, port: &mut SerialStream, control: u8) -> tokio_serial::Result<u8> {
+        let flow = match control {
+            FLOW_NONE => Some(FlowControl::None),
+            FLOW_SOFTWARE => Some(FlowControl::Software),
+            FLOW_HARDWARE => Some(FlowControl::Hardware),
+            _ => None,
+        };
+        if let Some(flow) = flow {
+            port.set_flow_control(flow)?;
+        }
+        match control {
+            BREAK_ON => port.set_break()?,
+            BREAK_OFF => port.clear_break()?,
+            DTR_ON | DTR_OFF => port.write_data_terminal_ready(control == DTR_ON)?,
+            RTS_ON | RTS_OFF => port.write_request_to_send(control == RTS_ON)?,
+            _ => {}
+        }
+        Ok(match control {
+            FLOW_QUERY..=FLOW_HARDWARE => com_port::flow_control(port.flow_control()?),
+            BREAK_QUERY..=BREAK_OFF => {
+                if control != BREAK_QUERY {
+                    self.brk = control == BREAK_ON;
+                }
+                if self.brk {
+                    BREAK_ON
+                } else {
+                    BREAK_OFF
+                }
+            }
+            DTR_QUERY..=DTR_OFF => {
+                if control != DTR_QUERY {
+                    self.dtr = control == DTR_ON;
+                }
+                if self.dtr {
+                    DTR_ON
+                } else {
+                    DTR_OFF
+                }
+            }
+            RTS_QUERY..=RTS_OFF => {
+                if control != RTS_QUERY {
+                    self.rts = control == RTS_ON;
+                }
+                if self.rts {
+                    RTS_ON
+                } else {
+                    RTS_OFF
+                }
+            }
+            control => control,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rfc2217::telnet::{IAC, SB, SE, WILL};
+
+    #[test]
+    fn session_test() {
+        let mut session = Session::new(ModemLines::from_state(0x30));
+        session.start();
+        let mut input = vec![b'a', IAC, WILL, COM_PORT_OPTION];
+        input.extend([IAC, SB, COM_PORT_OPTION, 1, 0, 0, 0x25, 0x80, IAC, SE]);
+        input.extend([IAC, SB, COM_PORT_OPTION, 11, IAC, IAC, IAC, SE]);
+        let requests = session.receive(&input);
+        assert_eq!(
+            requests,
+            vec![
+                Request::Data(vec![b'a']),
+                Request::Command(Command::SetBaudRate(9600)),
+                Request::Reply(Command::SetModemStateMask(0xff).encode(true)),
+                Request::Reply(Command::NotifyModemState(0x30).encode(true)),
+            ]
+        );
+        assert_eq!(session.modem_state(ModemLines::from_state(0x30)), None);
+        assert_eq!(
+            session.modem_state(ModemLines::from_state(0x20)),
+            Some(Command::NotifyModemState(0x21).encode(true))
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+
+pub const BINARY: u8 = 0;
+pub const SGA: u8 = 3;
+pub const COM_PORT_OPTION: u8 = 44;
+
+// What a Telnet stream carries besides plain data.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Data(Vec<u8>),
+    Negotiate(u8, u8),
+    Subnegotiation(u8, Vec<u8>),
+}
+
+#[derive(Clone, Debug, Default)]
+enum State {
+    #[default]
+    Data,
+    Iac,
+    Verb(u8),
+    Sub(Vec<u8>),
+    SubIac(Vec<u8>),
+}
+
+// Splits a Telnet stream into data, option negotiations and
+// subnegotiations. Sequences cut between two reads are kept until the
+// next one.
+#[derive(Debug, Default)]
+pub struct Parser {
+    state: State,
+}
+
+impl Parser {
+    pub fn feed(&mut self, input: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut data = Vec::new();
+        for x in input {
+            self.state = match (std::mem::take(&mut self.state), *x) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, x) => {
+                    data.push(x);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, verb @ (DO | DONT | WILL | WONT)) => State::Verb(verb),
+                (State::Iac, SB) => State::Sub(Vec::new()),
+                // Other commands (NOP, GA, ...) carry nothing we need.
+                (State::Iac, _) => State::Data,
+                (State::Verb(verb), option) => {
+                    flush(&mut data, &mut events);
+                    events.push(Event::Negotiate(verb, option));
+                    State::Data
+                }
+                (State::Sub(sub), IAC) => State::SubIac(sub),
+                (State::Sub(mut sub), x) => {
+                    sub.push(x);
+                    State::Sub(sub)
+                }
+                (State::SubIac(mut sub), IAC) => {
+                    sub.push(IAC);
+                    State::Sub(sub)
+                }
+                (State::SubIac(sub), _) => {
+                    flush(&mut data, &mut events);
+                    if let Some((option, payload)) = sub.split_first() {
+                        events.push(Event::Subnegotiation(*option, payload.to_vec()));
+                    }
+                    State::Data
+                }
+            };
+        }
+        flush(&mut data, &mut events);
+        events
+    }
+}
+
+fn flush(data: &mut Vec<u8>, events: &mut Vec<Event>) {
+    if !data.is_empty() {
+        events.push(Event::Data(std::mem::take(data)));
+    }
+}
+
+// Doubles every IAC so that the data can be sent over Telnet.
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for x in data {
+        escaped.push(*x);
+        if *x == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
+
+pub fn subnegotiation(option: u8, payload: &[u8]) -> Vec<u8> {
+    let mut dst = vec![IAC, SB, option];
+    dst.extend(escape(payload));
+    dst.extend([IAC, SE]);
+    dst
+}
+
+// Keeps track of the options enabled on each side of the connection, so
+// that requests are answered without looping forever.
+#[derive(Debug)]
+pub struct Negotiation {
+    local: Vec<u8>,
+    remote: Vec<u8>,
+    local_on: HashSet<u8>,
+    remote_on: HashSet<u8>,
+}
+
+impl Negotiation {
+    // `local` are the options we are willing to perform, `remote` the ones
+    // we want the peer to perform.
+    pub fn new(local: &[u8], remote: &[u8]) -> Self {
+        Self {
+            local: local.to_vec(),
+            remote: remote.to_vec(),
+            local_on: HashSet::new(),
+            remote_on: HashSet::new(),
+        }
+    }
+
+    // Returns the requests that enable every supported option.
+    pub fn start(&mut self) -> Vec<u8> {
+        let mut dst = Vec::new();
+        for option in &self.local {
+            self.local_on.insert(*option);
+            dst.extend([IAC, WILL, *option]);
+        }
+        for option in &self.remote {
+            self.remote_on.insert(*option);
+            dst.extend([IAC, DO, *option]);
+        }
+        dst
+    }
+
+    // Returns the answer to a negotiation received from the peer, if any.
+    pub fn answer(&mut self, verb: u8, option: u8) -> Vec<u8> {
+        let reply = match verb {
+            DO if !self.local.contains(&option) => Some(WONT),
+            DO => self.local_on.insert(option).then_some(WILL),
+            DONT => self.local_on.remove(&option).then_some(WONT),
+            WILL if !self.remote.contains(&option) => Some(DONT),
+            WILL => self.remote_on.insert(option).then_some(DO),
+            WONT => self.remote_on.remove(&option).then_some(DONT),
+            _ => None,
+        };
+        reply
+            .map(|reply| vec![IAC, reply, option])
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parser_test() {
+        let mut parser = Parser::default();
+        assert_eq!(
+            parser.feed(&[b'a', IAC, IAC, b'b', IAC, WILL]),
+            vec![Event::Data(vec![b'a', IAC, b'b'])]
+        );
+        assert_eq!(
+            parser.feed(&[COM_PORT_OPTION, IAC, SB, COM_PORT_OPTION, 1, 0, 0]),
+            vec![Event::Negotiate(WILL, COM_PORT_OPTION)]
+        );
+        assert_eq!(
+            parser.feed(&[0x25, 0x80, IAC, SE, b'c']),
+            vec![
+                Event::Subnegotiation(COM_PORT_OPTION, vec![1, 0, 0, 0x25, 0x80]),
+                Event::Data(vec![b'c'])
+            ]
+        );
+    }
+
+    #[test]
+    fn negotiation_test() {
+        let mut negotiation = Negotiation::new(&[BINARY], &[COM_PORT_OPTION]);
+        assert_eq!(
+            negotiation.start(),
+            vec![IAC, WILL, BINARY, IAC, DO, COM_PORT_OPTION]
+        );
+        assert_eq!(negotiation.answer(DO, BINARY), Vec::<u8>::new());
+        assert_eq!(negotiation.answer(WILL, COM_PORT_OPTION), Vec::<u8>::new());
+        assert_eq!(negotiation.answer(DO, SGA), vec![IAC, WONT, SGA]);
+        assert_eq!(negotiation.answer(DONT, BINARY), vec![IAC, WONT, BINARY]);
+        assert_eq!(negotiation.answer(DO, BINARY), vec![IAC, WILL, BINARY]);
+    }
+}
@@ -0,0 +1,83 @@
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use serial_monitor::atellica::{BuildError, Info};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time;
+use tokio_util::codec::Decoder;
+
+use crate::codec::Codec;
+use crate::compose;
+use crate::error::ProgramError;
+use crate::interface::serial;
+use crate::opt::{Opt, SendOpt};
+
+// Sends the frames, then the messages, to the port, through the link layer
+// with --link, and displays them along with whatever the port answers.
+// Returns once every frame was acknowledged or given up on and the port
+// then stayed quiet for --wait-ms, or once a trigger match stopped it, with
+// the code to exit with then.
+pub async fn run(path: &str, opt: &Opt, send: &SendOpt) -> Result<Option<i32>, ProgramError> {
+    let mut frames: Vec<Bytes> = send
+        .frames
+        .iter()
+        .map(|frame| Bytes::copy_from_slice(frame))
+        .collect();
+    for mut message in
+        compose::build_messages(&send.message).map_err(ProgramError::InvalidMessage)?
+    {
+        let frame = message
+            .encode()
+            .ok_or(ProgramError::InvalidMessage(BuildError::Encode))?;
+        frames.push(frame.freeze());
+    }
+
+    let port = serial::open(path, opt)?;
+    let mut reader = Codec::new(path.to_string(), opt)
+        .with_link(opt.link_settings())
+        .framed(port);
+    let mut tx = Codec::new(path.to_string(), opt).with_direction("tx");
+
+    for frame in frames {
+        reader.codec_mut().send(frame);
+    }
+
+    let wait = Duration::from_millis(send.wait_ms);
+    while reader.codec().exit_code().is_none() {
+        for bytes in reader.codec_mut().take_outgoing() {
+            reader
+                .get_mut()
+                .write_all(&bytes)
+                .await
+                .map_err(ProgramError::IoError)?;
+            tx.display(&BytesMut::from(&bytes[..]));
+        }
+
+        let waiting = !reader.codec().link_idle();
+        let deadline = match reader.codec().link_deadline() {
+            Some(deadline) if waiting => deadline.into(),
+            // Decoding only yields when the link has something to write,
+            // so the codec tells when the port last sent anything.
+            _ => reader.codec().last_read() + wait,
+        };
+        tokio::select! {
+            next = reader.next() => match next {
+                None => break,
+                Some(Err(err)) => reader.codec_mut().error(&err),
+                Some(Ok(_)) => {}
+            },
+            _ = time::sleep_until(deadline) => {
+                if waiting {
+                    reader.codec_mut().poll_link();
+                } else if reader.codec().last_read() + wait <= time::Instant::now() {
+                    // Bytes may have come in while the read was pending.
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut pending = reader.read_buffer_mut().split();
+    reader.codec_mut().flush(&mut pending);
+    Ok(reader.codec().exit_code())
+}
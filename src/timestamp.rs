@@ -0,0 +1,78 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, Utc};
+use std::time::{Duration, Instant};
+
+use crate::opt::{Opt, TimestampOpt};
+
+const TIME_FORMAT: &str = "%X:%6f";
+const LOCAL_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+const UTC_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6fZ";
+
+// Keeps track of the instants needed to stamp each chunk read from a port.
+pub struct Timestamp {
+    mode: TimestampOpt,
+    format: Option<String>,
+    start: Instant,
+    last: Instant,
+}
+
+// Returns true if the strftime string can be used by chrono without failing.
+pub fn is_valid_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
+// Formats a duration as seconds with microsecond precision, e.g. "+1.000250".
+fn format_elapsed(elapsed: Duration) -> String {
+    format!("+{}.{:06}", elapsed.as_secs(), elapsed.subsec_micros())
+}
+
+impl Timestamp {
+    pub fn new(opt: &Opt) -> Self {
+        let now = Instant::now();
+        Self {
+            mode: opt.timestamp,
+            format: opt.timestamp_format.clone(),
+            start: now,
+            last: now,
+        }
+    }
+
+    // Returns the timestamp for a chunk received now.
+    pub fn stamp(&mut self) -> String {
+        let now = Instant::now();
+        let previous = std::mem::replace(&mut self.last, now);
+        match self.mode {
+            TimestampOpt::Time => Local::now()
+                .format(self.format.as_deref().unwrap_or(TIME_FORMAT))
+                .to_string(),
+            TimestampOpt::Local => Local::now()
+                .format(self.format.as_deref().unwrap_or(LOCAL_FORMAT))
+                .to_string(),
+            TimestampOpt::Utc => Utc::now()
+                .format(self.format.as_deref().unwrap_or(UTC_FORMAT))
+                .to_string(),
+            TimestampOpt::Session => format_elapsed(now - self.start),
+            TimestampOpt::Delta => format_elapsed(now - previous),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_elapsed_test() {
+        assert_eq!(
+            format_elapsed(Duration::from_micros(1_000_250)),
+            "+1.000250"
+        );
+        assert_eq!(format_elapsed(Duration::from_millis(12)), "+0.012000");
+    }
+
+    #[test]
+    fn is_valid_format_test() {
+        assert!(is_valid_format("%H:%M:%S%.3f"));
+        assert!(!is_valid_format("%Q"));
+    }
+}
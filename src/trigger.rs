@@ -0,0 +1,305 @@
+use regex_lite::Regex;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::str::FromStr;
+use std::{ascii, io};
+
+use crate::opt::Opt;
+
+// Number of bytes kept from previous chunks so that regex triggers can
+// match across chunk boundaries.
+const REGEX_WINDOW: usize = 256;
+
+#[derive(Clone, Debug)]
+enum Pattern {
+    Bytes(Vec<u8>),
+    Regex(Regex),
+}
+
+/// A byte sequence or regex to watch for in the received stream
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    text: String,
+    pattern: Pattern,
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = match s.strip_prefix("re:") {
+            Some(re) => Pattern::Regex(Regex::new(re).map_err(|e| e.to_string())?),
+            None => Pattern::Bytes(parse_hex(s)?),
+        };
+        Ok(Self {
+            text: s.to_string(),
+            pattern,
+        })
+    }
+}
+
+// Parses hex bytes written as "02 41 03", "0x02,0x41,0x03" or "024103".
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|x| x.trim_start_matches("0x").trim_start_matches("0X"))
+        .collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("invalid hex bytes '{}'", s));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("invalid hex bytes '{}'", s))
+        })
+        .collect()
+}
+
+// Escapes the bytes like the char codec does and returns, for every byte,
+// the index of its first character in the escaped text.
+fn escape(bytes: &[u8]) -> (String, Vec<usize>) {
+    let mut text = String::new();
+    let mut starts = Vec::with_capacity(bytes.len());
+    for x in bytes {
+        starts.push(text.len());
+        text += &ascii::escape_default(*x).to_string();
+    }
+    (text, starts)
+}
+
+/// A trigger match, as absolute offsets into the stream of a port
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    pub trigger: usize,
+    pub range: Range<usize>,
+}
+
+// Finds trigger patterns in a stream of bytes fed a chunk at a time.
+struct Matcher {
+    triggers: Vec<Trigger>,
+    window: Vec<u8>,
+    window_start: usize,
+    reported: Vec<usize>,
+}
+
+impl Matcher {
+    fn new(triggers: Vec<Trigger>) -> Self {
+        let reported = vec![0; triggers.len()];
+        Self {
+            triggers,
+            window: Vec::new(),
+            window_start: 0,
+            reported,
+        }
+    }
+
+    // Returns the number of bytes to keep between chunks.
+    fn window_len(&self) -> usize {
+        self.triggers
+            .iter()
+            .map(|trigger| match &trigger.pattern {
+                Pattern::Bytes(bytes) => bytes.len() - 1,
+                Pattern::Regex(_) => REGEX_WINDOW,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Returns the matches that include at least one byte of the chunk.
+    fn feed(&mut self, chunk: &[u8]) -> Vec<Match> {
+        let new_start = self.window_start + self.window.len();
+        self.window.extend_from_slice(chunk);
+
+        let mut matches = Vec::new();
+        for (index, trigger) in self.triggers.iter().enumerate() {
+            let ranges: Vec<Range<usize>> = match &trigger.pattern {
+                Pattern::Bytes(bytes) => self
+                    .window
+                    .windows(bytes.len())
+                    .enumerate()
+                    .filter(|(_, window)| window == bytes)
+                    .map(|(start, _)| start..start + bytes.len())
+                    .collect(),
+                Pattern::Regex(re) => {
+                    let (text, starts) = escape(&self.window);
+                    re.find_iter(&text)
+                        .filter(|m| !m.is_empty())
+                        .map(|m| {
+                            starts.partition_point(|x| *x <= m.start()) - 1
+                                ..starts.partition_point(|x| *x < m.end())
+                        })
+                        .collect()
+                }
+            };
+            for range in ranges {
+                let range = range.start + self.window_start..range.end + self.window_start;
+                if range.end > new_start && range.start >= self.reported[index] {
+                    self.reported[index] = range.end;
+                    matches.push(Match {
+                        trigger: index,
+                        range,
+                    });
+                }
+            }
+        }
+
+        let keep = self.window_len().min(self.window.len());
+        let drop = self.window.len() - keep;
+        self.window.drain(..drop);
+        self.window_start += drop;
+
+        matches
+    }
+}
+
+/// Trigger patterns together with what to do when they match
+pub struct Triggers {
+    matcher: Matcher,
+    counts: Vec<usize>,
+    context: Option<usize>,
+    exec: Option<String>,
+    exit: Option<i32>,
+    // The exit code, once a match stopped the monitor.
+    stopped: Option<i32>,
+    before: VecDeque<String>,
+    after: usize,
+}
+
+impl Triggers {
+    pub fn new(opt: &Opt) -> Self {
+        Self {
+            matcher: Matcher::new(opt.trigger.clone()),
+            counts: vec![0; opt.trigger.len()],
+            context: opt.trigger_context,
+            exec: opt.trigger_exec.clone(),
+            exit: opt.trigger_exit,
+            stopped: None,
+            before: VecDeque::new(),
+            after: 0,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<Match> {
+        if self.matcher.triggers.is_empty() {
+            return Vec::new();
+        }
+        self.matcher.feed(chunk)
+    }
+
+    // Prints a line that had no match, unless only the context around
+    // matches is shown and the line is too far from one.
+    pub fn show(&mut self, line: String) {
+        match self.context {
+            None => println!("{}", line),
+            Some(_) if self.after > 0 => {
+                self.after -= 1;
+                println!("{}", line);
+            }
+            Some(context) => {
+                self.before.push_back(line);
+                if self.before.len() > context {
+                    self.before.pop_front();
+                }
+            }
+        }
+    }
+
+    // Prints a line that had matches, preceded by its context.
+    pub fn show_match(&mut self, line: String) {
+        self.before.drain(..).for_each(|line| println!("{}", line));
+        println!("{}", line);
+        self.after = self.context.unwrap_or(0);
+    }
+
+    // Counts a match and runs its actions. Returns the description of the
+    // match to print, or an error if the command could not be started.
+    pub fn fire(&mut self, port: &str, found: &Match) -> Result<String, io::Error> {
+        self.counts[found.trigger] += 1;
+        let trigger = &self.matcher.triggers[found.trigger].text;
+        let description = format!(
+            "trigger '{}' matched at offset 0x{:x} ({} times)",
+            trigger, found.range.start, self.counts[found.trigger]
+        );
+
+        if let Some(command) = &self.exec {
+            #[cfg(unix)]
+            let mut shell = tokio::process::Command::new("sh");
+            #[cfg(unix)]
+            shell.arg("-c");
+            #[cfg(windows)]
+            let mut shell = tokio::process::Command::new("cmd");
+            #[cfg(windows)]
+            shell.arg("/C");
+
+            shell
+                .arg(command)
+                .env("SERIAL_MONITOR_PORT", port)
+                .env("SERIAL_MONITOR_TRIGGER", trigger)
+                .env("SERIAL_MONITOR_OFFSET", found.range.start.to_string())
+                .spawn()?;
+        }
+
+        Ok(description)
+    }
+
+    // Stops the monitor with the requested code once a match has been
+    // reported.
+    pub fn stop(&mut self) {
+        self.stopped = self.stopped.or(self.exit);
+    }
+
+    // The code to exit with once a match stopped the monitor. The loop
+    // reading the port returns it after closing the port.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_test() {
+        assert_eq!(parse_hex("02 41 03"), Ok(vec![0x02, 0x41, 0x03]));
+        assert_eq!(parse_hex("0x02,0x41"), Ok(vec![0x02, 0x41]));
+        assert_eq!(parse_hex("0241"), Ok(vec![0x02, 0x41]));
+        assert!(parse_hex("024").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[test]
+    fn bytes_across_chunks_test() {
+        let mut matcher = Matcher::new(vec!["02 03".parse().unwrap()]);
+        assert_eq!(matcher.feed(&[0x01, 0x02]), vec![]);
+        assert_eq!(
+            matcher.feed(&[0x03, 0x02, 0x03]),
+            vec![
+                Match {
+                    trigger: 0,
+                    range: 1..3
+                },
+                Match {
+                    trigger: 0,
+                    range: 3..5
+                }
+            ]
+        );
+        assert_eq!(matcher.feed(&[0x03]), vec![]);
+    }
+
+    #[test]
+    fn regex_across_chunks_test() {
+        let mut matcher = Matcher::new(vec![r"re:ERR\\x0[0-9]".parse().unwrap()]);
+        assert_eq!(matcher.feed(b"ok ER"), vec![]);
+        assert_eq!(
+            matcher.feed(b"R\x05 ok"),
+            vec![Match {
+                trigger: 0,
+                range: 3..7
+            }]
+        );
+        assert_eq!(matcher.feed(b" ok"), vec![]);
+    }
+}